@@ -0,0 +1,164 @@
+use crate::scan_job;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use scan_job::breakdown::TimeField;
+use scan_job::navigator::{Navigator, NavigatorExit};
+use scan_job::scan_cache::ScanCache;
+use scan_job::scan_job_args::{ColorChoice, Command, OutputFormat, ScanJobArgs};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn parent_dir(directory: &str) -> Option<String> {
+    let path = Path::new(directory);
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => Some(parent.to_path_buf()),
+        _ => path
+            .canonicalize()
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf)),
+    }
+    .map(|parent| parent.to_string_lossy().to_string())
+}
+
+// Chart options given before a subcommand would be silently ignored, and a
+// directory there would be taken for the subcommand's
+fn reject_args_before_subcommand(matches: &ArgMatches) {
+    let Some((name, _)) = matches.subcommand() else {
+        return;
+    };
+    let mut command = ScanJobArgs::command();
+    let given = command
+        .get_arguments()
+        .filter(|arg| !arg.is_global_set())
+        .find(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .map(|arg| match arg.get_long() {
+            Some(long) => format!("--{}", long),
+            None => arg.get_id().as_str().to_uppercase(),
+        });
+    if let Some(given) = given {
+        command
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!("'{}' can't be used with the {} subcommand", given, name),
+            )
+            .exit()
+    }
+}
+
+/// Runs the df2 command line with the process arguments.
+pub fn run() {
+    let matches = ScanJobArgs::command().get_matches();
+    reject_args_before_subcommand(&matches);
+    let mut args = ScanJobArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    match args.color {
+        ColorChoice::Auto => {}
+        ColorChoice::Always => colored::control::set_override(true),
+        ColorChoice::Never => colored::control::set_override(false),
+    }
+    if let Some(command) = &args.command {
+        let result = match command {
+            Command::Dupes(dupes) => scan_job::show_duplicates(dupes, args.verbose),
+            Command::Diff(diff) => scan_job::show_diff(diff, args.verbose),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if (args.watch.is_some() || args.live) && args.is_plain() {
+        eprintln!("Watch mode needs a terminal");
+        std::process::exit(1);
+    }
+    if args.interactive_mode && args.is_plain() {
+        eprintln!("Interactive mode needs a terminal, printing the results instead");
+        args.interactive_mode = false;
+    }
+    let options = args.scan_options().unwrap_or_else(|e| {
+        ScanJobArgs::command()
+            .error(clap::error::ErrorKind::ValueValidation, e)
+            .exit()
+    });
+    let loaded_tree = args.load.as_ref().map(|path| {
+        let (metadata, tree) = scan_job::import::load_dump(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load '{}': {}", path.display(), e);
+            std::process::exit(1);
+        });
+        // Files without an access time are left out of the age groups
+        if args.by_age == Some(TimeField::Atime) && tree.newest.atime.is_none() {
+            ScanJobArgs::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    format!(
+                        "'{}' has no access times to group by with --by-age=atime",
+                        path.display()
+                    ),
+                )
+                .exit()
+        }
+        (metadata, tree)
+    });
+
+    if args.output != OutputFormat::Console {
+        let result = match &loaded_tree {
+            Some((metadata, tree)) => scan_job::write_export(args.output, metadata, tree),
+            None => scan_job::export_dir(args),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.live {
+        let fallback = args.watch.unwrap_or(Duration::from_secs(30));
+        scan_job::live_dir(args, options, fallback);
+        return;
+    }
+    if let Some(interval) = args.watch {
+        scan_job::watch_dir(args, options, interval);
+        return;
+    }
+
+    let cache = Arc::new(match args.no_cache {
+        true => ScanCache::in_memory(),
+        false => ScanCache::load(),
+    });
+
+    let is_loaded = loaded_tree.is_some();
+    let mut tree = match loaded_tree {
+        Some((_, tree)) => {
+            args.directory = tree.name.clone();
+            if !args.interactive_mode {
+                scan_job::show_tree(args.clone(), &tree);
+            }
+            Some(tree)
+        }
+        None => scan_job::scan_dir(args.clone(), options.clone(), cache.clone()),
+    };
+
+    if args.interactive_mode {
+        while let Some(root) = tree.take() {
+            let parent = parent_dir(&args.directory).filter(|_| !is_loaded);
+            match Navigator::new(args.clone(), root, parent.is_some(), !is_loaded).run() {
+                Ok(NavigatorExit::Quit) => break,
+                Ok(NavigatorExit::Parent) => {
+                    args.directory = parent.unwrap();
+                    tree = scan_job::scan_dir(args.clone(), options.clone(), cache.clone());
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    if !is_loaded {
+        if let Err(e) = cache.save() {
+            eprintln!("Failed to save scan cache: {}", e);
+        }
+    }
+}
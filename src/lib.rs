@@ -0,0 +1,11 @@
+mod cli;
+mod scan_job;
+
+#[doc(hidden)]
+pub use cli::run;
+pub use scan_job::dupes::DuplicateSet;
+pub use scan_job::file_util::{EntryTimes, HardLinkPolicy, SizeMode, ThreadCount, Usage};
+pub use scan_job::scan_tree::{FileStat, NodeKind, ScanNode};
+pub use scan_job::scanner::{ScanResult, Scanner};
+pub use scan_job::top_files::TopFile;
+pub use scan_job::{IgnoreMode, ScanMetadata};
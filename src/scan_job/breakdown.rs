@@ -0,0 +1,229 @@
+use super::file_util::Usage;
+use super::owners;
+use super::scan_tree::{NodeKind, ScanNode};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAY: i64 = 24 * 60 * 60;
+
+// Upper bound of each age bucket, youngest first. Anything older goes into the last one
+const AGE_BUCKETS: [(i64, &str); 5] = [
+    (DAY, "< 1 day"),
+    (7 * DAY, "< 1 week"),
+    (30 * DAY, "< 1 month"),
+    (182 * DAY, "< 6 months"),
+    (365 * DAY, "< 1 year"),
+];
+const OLDEST_BUCKET: &str = "> 1 year";
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, ValueEnum)]
+pub enum TimeField {
+    /// Last modification
+    Mtime,
+    /// Last access
+    Atime,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, ValueEnum)]
+pub enum OwnerField {
+    /// Owning user
+    User,
+    /// Owning group
+    Group,
+}
+
+/// Total usage of the files in one group of a breakdown.
+#[derive(Debug, Clone)]
+pub struct BreakdownGroup {
+    pub label: String,
+    pub usage: Usage,
+    /// Fixed position of the group, for breakdowns whose groups have a
+    /// natural order.
+    pub rank: Option<usize>,
+}
+
+/// A way of grouping every file below a directory, regardless of where in
+/// the tree it is.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Breakdown {
+    Type,
+    Age(TimeField),
+    Owner(OwnerField),
+}
+
+impl Breakdown {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Breakdown::Type => "file type",
+            Breakdown::Age(TimeField::Mtime) => "modification time",
+            Breakdown::Age(TimeField::Atime) => "access time",
+            Breakdown::Owner(OwnerField::User) => "owner",
+            Breakdown::Owner(OwnerField::Group) => "group",
+        }
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        matches!(self, Breakdown::Age(_))
+    }
+
+    /// Total usage of each group, in rank order for ordered breakdowns and
+    /// in no particular order otherwise. Ordered breakdowns report every
+    /// group, even empty ones.
+    pub fn group(&self, node: &ScanNode) -> Vec<BreakdownGroup> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs() as i64);
+        let mut groups = HashMap::new();
+        self.collect(node, now, &mut groups);
+
+        match self {
+            Breakdown::Type | Breakdown::Owner(_) => groups
+                .into_iter()
+                .map(|(label, usage)| BreakdownGroup {
+                    label,
+                    usage,
+                    rank: None,
+                })
+                .collect(),
+            Breakdown::Age(_) => AGE_BUCKETS
+                .iter()
+                .map(|(_, label)| *label)
+                .chain([OLDEST_BUCKET])
+                .enumerate()
+                .map(|(rank, label)| BreakdownGroup {
+                    label: label.to_string(),
+                    usage: groups.remove(label).unwrap_or_default(),
+                    rank: Some(rank),
+                })
+                .collect(),
+        }
+    }
+
+    fn collect(&self, node: &ScanNode, now: i64, groups: &mut HashMap<String, Usage>) {
+        for child in &node.children {
+            match child.kind {
+                NodeKind::Directory => self.collect(child, now, groups),
+                NodeKind::File | NodeKind::Symlink => {
+                    if let Some(key) = self.key(child, now) {
+                        *groups.entry(key).or_default() += child.usage;
+                    }
+                }
+            }
+        }
+    }
+
+    // Files without the information to group them by are left out
+    fn key(&self, file: &ScanNode, now: i64) -> Option<String> {
+        match self {
+            Breakdown::Type => Some(match Path::new(&file.name).extension() {
+                Some(extension) => format!(".{}", extension.to_string_lossy().to_lowercase()),
+                None => String::from("(no extension)"),
+            }),
+            Breakdown::Age(field) => {
+                let time = match field {
+                    TimeField::Mtime => file.mtime?,
+                    TimeField::Atime => file.atime?,
+                };
+                let age = now - time;
+                let label = AGE_BUCKETS
+                    .iter()
+                    .find(|(limit, _)| age < *limit)
+                    .map_or(OLDEST_BUCKET, |(_, label)| label);
+                Some(label.to_string())
+            }
+            Breakdown::Owner(OwnerField::User) => file.uid.map(owners::user_name),
+            Breakdown::Owner(OwnerField::Group) => file.gid.map(owners::group_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_job::scan_tree::{dir, file};
+
+    fn owned_file(name: &str, size: u64, mtime: i64, uid: u32) -> ScanNode {
+        ScanNode {
+            mtime: Some(mtime),
+            uid: Some(uid),
+            gid: Some(uid),
+            ..file(name, size)
+        }
+    }
+
+    fn tree() -> ScanNode {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        dir(
+            ".",
+            vec![
+                owned_file("a.RS", 10, now, 0),
+                dir(
+                    "sub",
+                    vec![
+                        owned_file("b.rs", 20, now - 3 * DAY, 0),
+                        owned_file("Makefile", 5, now - 2 * 365 * DAY, 4242),
+                    ],
+                ),
+            ],
+        )
+    }
+
+    fn sizes(mut groups: Vec<BreakdownGroup>) -> Vec<(String, u64)> {
+        groups.sort_by(|a, b| a.label.cmp(&b.label));
+        groups
+            .into_iter()
+            .map(|group| (group.label, group.usage.apparent))
+            .collect()
+    }
+
+    #[test]
+    fn groups_files_by_extension() {
+        let groups = Breakdown::Type.group(&tree());
+        assert!(groups.iter().all(|group| group.rank.is_none()));
+        assert_eq!(
+            sizes(groups),
+            [
+                (String::from("(no extension)"), 5),
+                (String::from(".rs"), 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn ranks_every_age_bucket() {
+        let groups = Breakdown::Age(TimeField::Mtime).group(&tree());
+        let ranked: Vec<_> = groups
+            .iter()
+            .map(|group| (group.rank, group.label.as_str(), group.usage.apparent))
+            .collect();
+        assert_eq!(
+            ranked,
+            [
+                (Some(0), "< 1 day", 10),
+                (Some(1), "< 1 week", 20),
+                (Some(2), "< 1 month", 0),
+                (Some(3), "< 6 months", 0),
+                (Some(4), "< 1 year", 0),
+                (Some(5), "> 1 year", 5),
+            ]
+        );
+
+        // Files without an access time are left out
+        let groups = Breakdown::Age(TimeField::Atime).group(&tree());
+        assert!(groups.iter().all(|group| group.usage == Usage::default()));
+    }
+
+    #[test]
+    fn groups_files_by_owner() {
+        let groups = Breakdown::Owner(OwnerField::User).group(&tree());
+        assert!(groups.iter().all(|group| group.rank.is_none()));
+        let mut expected = vec![(owners::user_name(0), 30), (owners::user_name(4242), 5)];
+        expected.sort();
+        assert_eq!(sizes(groups), expected);
+    }
+}
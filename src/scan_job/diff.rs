@@ -0,0 +1,191 @@
+use super::file_util::{SizeMode, Usage};
+use super::scan_tree::{NodeKind, ScanNode};
+use bytesize::ByteSize;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DiffStatus {
+    Added,
+    Removed,
+    Grown,
+    Shrunk,
+    // Same total, but something inside was added, removed or resized
+    Changed,
+    Unchanged,
+}
+
+impl DiffStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            DiffStatus::Added => "added",
+            DiffStatus::Removed => "removed",
+            DiffStatus::Grown => "grown",
+            DiffStatus::Shrunk => "shrunk",
+            DiffStatus::Changed => "changed",
+            DiffStatus::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// An entry of either scan, with its usage in each.
+#[derive(Debug, Clone)]
+pub struct DiffNode {
+    pub name: String,
+    pub kind: NodeKind,
+    pub old: Option<Usage>,
+    pub new: Option<Usage>,
+    /// Children that changed, largest change first.
+    pub children: Vec<DiffNode>,
+}
+
+impl DiffNode {
+    /// Compares two scans of the same directory. Their root names may differ.
+    pub fn new(old: &ScanNode, new: &ScanNode, size_mode: SizeMode) -> Self {
+        Self::compare(new.name.clone(), Some(old), Some(new), size_mode)
+    }
+
+    fn compare(
+        name: String,
+        old: Option<&ScanNode>,
+        new: Option<&ScanNode>,
+        size_mode: SizeMode,
+    ) -> Self {
+        let mut entries: HashMap<&str, (Option<&ScanNode>, Option<&ScanNode>)> = HashMap::new();
+        for child in old.iter().flat_map(|node| &node.children) {
+            entries.entry(&child.name).or_default().0 = Some(child);
+        }
+        for child in new.iter().flat_map(|node| &node.children) {
+            entries.entry(&child.name).or_default().1 = Some(child);
+        }
+
+        let mut children: Vec<_> = entries
+            .into_iter()
+            .map(|(name, (old, new))| Self::compare(name.to_string(), old, new, size_mode))
+            .filter(|child| child.status(size_mode) != DiffStatus::Unchanged)
+            .collect();
+        children.sort_by(|a, b| {
+            b.change(size_mode)
+                .unsigned_abs()
+                .cmp(&a.change(size_mode).unsigned_abs())
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        Self {
+            name,
+            kind: new.or(old).map_or(NodeKind::Directory, |node| node.kind),
+            old: old.map(|node| node.usage),
+            new: new.map(|node| node.usage),
+            children,
+        }
+    }
+
+    pub fn old_size(&self, size_mode: SizeMode) -> u64 {
+        self.old.map_or(0, |usage| usage.get(size_mode))
+    }
+
+    pub fn new_size(&self, size_mode: SizeMode) -> u64 {
+        self.new.map_or(0, |usage| usage.get(size_mode))
+    }
+
+    pub fn change(&self, size_mode: SizeMode) -> i64 {
+        self.new_size(size_mode) as i64 - self.old_size(size_mode) as i64
+    }
+
+    pub fn status(&self, size_mode: SizeMode) -> DiffStatus {
+        match (self.old, self.new) {
+            (None, _) => DiffStatus::Added,
+            (_, None) => DiffStatus::Removed,
+            _ => match self.change(size_mode) {
+                change if change > 0 => DiffStatus::Grown,
+                change if change < 0 => DiffStatus::Shrunk,
+                _ if self.children.is_empty() => DiffStatus::Unchanged,
+                _ => DiffStatus::Changed,
+            },
+        }
+    }
+}
+
+/// Size change with an explicit sign, like "+1.2 MiB".
+pub fn format_change(change: i64) -> String {
+    let sign = match change {
+        change if change < 0 => "-",
+        0 => "±",
+        _ => "+",
+    };
+    format!("{}{}", sign, ByteSize::b(change.unsigned_abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_job::scan_tree::{dir, file};
+
+    fn statuses(node: &DiffNode) -> Vec<(&str, DiffStatus, i64)> {
+        node.children
+            .iter()
+            .map(|child| {
+                let status = child.status(SizeMode::Apparent);
+                (
+                    child.name.as_str(),
+                    status,
+                    child.change(SizeMode::Apparent),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lists_changes_largest_first() {
+        let old = dir(
+            "old.json",
+            vec![
+                file("same", 10),
+                file("removed", 5),
+                file("grown", 10),
+                dir("moved", vec![file("a", 3), file("b", 7)]),
+            ],
+        );
+        let new = dir(
+            ".",
+            vec![
+                file("same", 10),
+                file("added", 50),
+                file("grown", 30),
+                dir("moved", vec![file("a", 7), file("b", 3)]),
+            ],
+        );
+
+        let diff = DiffNode::new(&old, &new, SizeMode::Apparent);
+        assert_eq!(diff.name, ".");
+        assert_eq!(diff.change(SizeMode::Apparent), 65);
+        assert_eq!(
+            statuses(&diff),
+            [
+                ("added", DiffStatus::Added, 50),
+                ("grown", DiffStatus::Grown, 20),
+                ("removed", DiffStatus::Removed, -5),
+                ("moved", DiffStatus::Changed, 0),
+            ]
+        );
+        let moved = &diff.children[3];
+        assert_eq!(
+            statuses(moved),
+            [("a", DiffStatus::Grown, 4), ("b", DiffStatus::Shrunk, -4)]
+        );
+    }
+
+    #[test]
+    fn identical_scans_have_no_changes() {
+        let tree = dir(".", vec![dir("sub", vec![file("a", 3)])]);
+        let diff = DiffNode::new(&tree, &tree, SizeMode::Disk);
+        assert!(diff.children.is_empty());
+        assert_eq!(diff.status(SizeMode::Disk), DiffStatus::Unchanged);
+    }
+
+    #[test]
+    fn formats_signed_changes() {
+        assert_eq!(format_change(1024), "+1.0 KiB");
+        assert_eq!(format_change(-1024), "-1.0 KiB");
+        assert_eq!(format_change(0), "±0 B");
+    }
+}
@@ -0,0 +1,238 @@
+use super::file_util::ErrorHandler;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Bytes read from the start of every candidate before hashing whole files
+const PARTIAL_HASH_SIZE: u64 = 4096;
+
+/// Files with identical contents.
+#[derive(Debug, Clone)]
+pub struct DuplicateSet {
+    /// Size of each copy.
+    pub size: u64,
+    /// Every copy, sorted by path.
+    pub paths: Vec<String>,
+}
+
+impl DuplicateSet {
+    /// Bytes that would be freed by keeping a single copy.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+// Files seen by the walker, grouped by size
+#[derive(Debug, Default)]
+pub struct DupeCandidates {
+    enabled: bool,
+    by_size: DashMap<u64, Vec<PathBuf>>,
+}
+
+impl DupeCandidates {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Adds a file, once per inode.
+    pub fn offer<F>(&self, path: F, metadata: &fs::Metadata)
+    where
+        F: FnOnce() -> PathBuf,
+    {
+        if !self.enabled || !metadata.is_file() || metadata.len() == 0 {
+            return;
+        }
+
+        self.by_size.entry(metadata.len()).or_default().push(path());
+    }
+
+    /// Narrows the candidates down to confirmed duplicates, most wasted space first.
+    pub fn confirm(&self, threads: usize, on_error: &ErrorHandler) -> Vec<DuplicateSet> {
+        let groups = self
+            .by_size
+            .iter()
+            .filter(|entry| entry.value().len() > 1)
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        let groups = refine(groups, threads, on_error, |path, _| {
+            hash_file(path, Some(PARTIAL_HASH_SIZE))
+        });
+        // Files no larger than the partial read were already hashed in full
+        let groups = refine(groups, threads, on_error, |path, size| match size {
+            size if size <= PARTIAL_HASH_SIZE => Ok(blake3::Hash::from([0; 32])),
+            _ => hash_file(path, None),
+        });
+
+        let mut sets: Vec<_> = groups
+            .into_iter()
+            .map(|(size, paths)| {
+                let mut paths: Vec<_> = paths
+                    .into_iter()
+                    .map(|path| path.to_string_lossy().to_string())
+                    .collect();
+                paths.sort();
+                DuplicateSet { size, paths }
+            })
+            .collect();
+        sets.sort_by(|a, b| {
+            b.wasted()
+                .cmp(&a.wasted())
+                .then_with(|| a.paths.cmp(&b.paths))
+        });
+        sets
+    }
+}
+
+// Splits every group by the hash of its files, keeping groups that still have
+// more than one file
+fn refine<F>(
+    groups: Vec<(u64, Vec<PathBuf>)>,
+    threads: usize,
+    on_error: &ErrorHandler,
+    hash: F,
+) -> Vec<(u64, Vec<PathBuf>)>
+where
+    F: Fn(&Path, u64) -> io::Result<blake3::Hash> + Sync,
+{
+    let work: Vec<_> = groups
+        .into_iter()
+        .enumerate()
+        .flat_map(|(group, (size, paths))| paths.into_iter().map(move |path| (group, size, path)))
+        .collect();
+    let next = AtomicUsize::new(0);
+    let refined = Mutex::new(HashMap::<_, Vec<PathBuf>>::new());
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                while let Some((group, size, path)) = work.get(next.fetch_add(1, Ordering::Relaxed))
+                {
+                    match hash(path, *size) {
+                        Ok(file_hash) => refined
+                            .lock()
+                            .unwrap()
+                            .entry((*group, *size, file_hash))
+                            .or_default()
+                            .push(path.clone()),
+                        Err(e) => on_error(format!("Error reading '{}': {}", path.display(), e)),
+                    }
+                }
+            });
+        }
+    });
+
+    refined
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((_, size, _), paths)| (size, paths))
+        .collect()
+}
+
+fn hash_file(path: &Path, limit: Option<u64>) -> io::Result<blake3::Hash> {
+    let file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    match limit {
+        Some(limit) => io::copy(&mut file.take(limit), &mut hasher)?,
+        None => io::copy(&mut &file, &mut hasher)?,
+    };
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_job::scanner::Scanner;
+
+    fn no_errors(e: String) {
+        panic!("{}", e)
+    }
+
+    #[test]
+    fn confirms_identical_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        };
+        // Same size and first 4 KiB, different ending
+        let large = vec![7; PARTIAL_HASH_SIZE as usize + 10];
+        let mut different = large.clone();
+        *different.last_mut().unwrap() = 8;
+        let files = [
+            write("small1", b"abc"),
+            write("small2", b"abc"),
+            write("other", b"xyz"),
+            write("large1", &large),
+            write("large2", &large),
+            write("different", &different),
+            write("empty1", b""),
+            write("empty2", b""),
+        ];
+
+        let candidates = DupeCandidates::new(true);
+        for file in &files {
+            candidates.offer(|| file.clone(), &fs::metadata(file).unwrap());
+        }
+        let sets = candidates.confirm(2, &no_errors);
+        let names: Vec<Vec<_>> = sets
+            .iter()
+            .map(|set| {
+                set.paths
+                    .iter()
+                    .map(|path| Path::new(path).file_name().unwrap().to_owned())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(names, [["large1", "large2"], ["small1", "small2"]]);
+        assert_eq!(sets[0].wasted(), PARTIAL_HASH_SIZE + 10);
+    }
+
+    #[test]
+    fn ignores_offers_when_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a");
+        fs::write(&file, b"abc").unwrap();
+        let candidates = DupeCandidates::new(false);
+        candidates.offer(|| file.clone(), &fs::metadata(&file).unwrap());
+        candidates.offer(|| file.clone(), &fs::metadata(&file).unwrap());
+        assert!(candidates.confirm(1, &no_errors).is_empty());
+    }
+
+    #[test]
+    fn hard_links_are_not_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/file"), b"contents").unwrap();
+        fs::hard_link(root.join("a/file"), root.join("link")).unwrap();
+
+        let find = || {
+            Scanner::new(root.to_string_lossy())
+                .on_error(no_errors)
+                .find_duplicates()
+                .unwrap()
+        };
+        assert!(find().is_empty());
+
+        fs::write(root.join("copy"), b"contents").unwrap();
+        let sets = find();
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].paths.len(), 2);
+        assert_eq!(sets[0].size, 8);
+    }
+}
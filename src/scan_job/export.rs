@@ -0,0 +1,237 @@
+use super::scan_tree::{NodeKind, ScanNode};
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+const NCDU_MAJOR_VERSION: u32 = 1;
+const NCDU_MINOR_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanMetadata {
+    pub root: String,
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub error_count: u64,
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    schema_version: u32,
+    scan: &'a ScanMetadata,
+    tree: JsonNode<'a>,
+}
+
+struct JsonNode<'a> {
+    path: PathBuf,
+    node: &'a ScanNode,
+}
+
+struct JsonChildren<'a> {
+    path: &'a Path,
+    children: &'a [ScanNode],
+}
+
+// Files have their own sizes, and hard links say which file they link to so
+// that readers can count each file once. Directories have the sizes counted
+// in them
+impl Serialize for JsonNode<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let is_dir = self.node.kind == NodeKind::Directory;
+        let size = self.node.stat.map_or(self.node.usage, |stat| stat.size);
+        let link = self.node.stat.filter(|stat| stat.is_hard_link());
+        let mut state = serializer.serialize_struct("Node", 13)?;
+        state.serialize_field("path", &self.path.to_string_lossy())?;
+        state.serialize_field("type", &self.node.kind)?;
+        state.serialize_field("apparent_size", &size.apparent)?;
+        state.serialize_field("disk_size", &size.disk)?;
+        state.serialize_field("file_count", &self.node.usage.files)?;
+        state.serialize_field("mtime", &self.node.mtime)?;
+        state.serialize_field("atime", &self.node.atime)?;
+        state.serialize_field("uid", &self.node.uid)?;
+        state.serialize_field("gid", &self.node.gid)?;
+        match link {
+            Some(link) => {
+                state.serialize_field("dev", &link.dev)?;
+                state.serialize_field("ino", &link.ino)?;
+                state.serialize_field("nlink", &link.nlink)?;
+            }
+            None => {
+                state.skip_field("dev")?;
+                state.skip_field("ino")?;
+                state.skip_field("nlink")?;
+            }
+        }
+        if is_dir {
+            state.serialize_field(
+                "children",
+                &JsonChildren {
+                    path: &self.path,
+                    children: &self.node.children,
+                },
+            )?;
+        }
+        state.end()
+    }
+}
+
+impl Serialize for JsonChildren<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.children.len()))?;
+        for child in self.children {
+            seq.serialize_element(&JsonNode {
+                path: self.path.join(&child.name),
+                node: child,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+pub fn write_json<W: Write>(
+    writer: W,
+    metadata: &ScanMetadata,
+    tree: &ScanNode,
+) -> anyhow::Result<()> {
+    let export = JsonExport {
+        schema_version: SCHEMA_VERSION,
+        scan: metadata,
+        tree: JsonNode {
+            path: PathBuf::from(&tree.name),
+            node: tree,
+        },
+    };
+    serde_json::to_writer(writer, &export)?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct NcduHeader {
+    progname: &'static str,
+    progver: &'static str,
+    timestamp: u64,
+}
+
+#[derive(Serialize)]
+struct NcduInfo<'a> {
+    name: &'a str,
+    asize: u64,
+    dsize: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    atime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dev: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ino: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nlink: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    hlnkc: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    notreg: bool,
+}
+
+impl<'a> NcduInfo<'a> {
+    fn dir(name: &'a str, node: &ScanNode) -> Self {
+        Self {
+            name,
+            asize: 0,
+            dsize: 0,
+            mtime: node.mtime,
+            atime: node.atime,
+            uid: node.uid,
+            gid: node.gid,
+            dev: None,
+            ino: None,
+            nlink: None,
+            hlnkc: false,
+            notreg: false,
+        }
+    }
+
+    // Files have their own sizes. ncdu counts each hard-linked file once by
+    // its device and inode
+    fn file(node: &'a ScanNode) -> Self {
+        let size = node.stat.map_or(node.usage, |stat| stat.size);
+        let link = node.stat.filter(|stat| stat.is_hard_link());
+        Self {
+            name: &node.name,
+            asize: size.apparent,
+            dsize: size.disk,
+            mtime: node.mtime,
+            atime: node.atime,
+            uid: node.uid,
+            gid: node.gid,
+            dev: link.map(|link| link.dev),
+            ino: node.stat.map(|stat| stat.ino),
+            nlink: link.map(|link| link.nlink),
+            hlnkc: link.is_some(),
+            notreg: node.kind == NodeKind::Symlink,
+        }
+    }
+}
+
+// ncdu stores a directory as an array of its own info followed by its children
+struct NcduDir<'a> {
+    name: &'a str,
+    node: &'a ScanNode,
+}
+
+impl Serialize for NcduDir<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.node.children.len() + 1))?;
+        seq.serialize_element(&NcduInfo::dir(self.name, self.node))?;
+        for child in &self.node.children {
+            match child.kind {
+                NodeKind::Directory => seq.serialize_element(&NcduDir {
+                    name: &child.name,
+                    node: child,
+                })?,
+                NodeKind::File | NodeKind::Symlink => {
+                    seq.serialize_element(&NcduInfo::file(child))?
+                }
+            }
+        }
+        seq.end()
+    }
+}
+
+pub fn write_ncdu<W: Write>(
+    writer: W,
+    metadata: &ScanMetadata,
+    tree: &ScanNode,
+) -> anyhow::Result<()> {
+    let header = NcduHeader {
+        progname: env!("CARGO_PKG_NAME"),
+        progver: env!("CARGO_PKG_VERSION"),
+        timestamp: metadata.started_at,
+    };
+    // ncdu names the root by its absolute path
+    let root = Path::new(&tree.name);
+    let root = fs::canonicalize(root)
+        .or_else(|_| std::path::absolute(root))
+        .map(|root| root.to_string_lossy().to_string())
+        .unwrap_or_else(|_| tree.name.clone());
+    serde_json::to_writer(
+        writer,
+        &(
+            NCDU_MAJOR_VERSION,
+            NCDU_MINOR_VERSION,
+            header,
+            NcduDir {
+                name: &root,
+                node: tree,
+            },
+        ),
+    )?;
+    Ok(())
+}
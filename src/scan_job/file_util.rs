@@ -1,77 +1,537 @@
-use crossbeam::channel::{unbounded, Receiver, Sender};
-use dashmap::DashSet;
-use std::collections::HashMap;
+use super::breakdown::BreakdownGroup;
+use super::dupes::DupeCandidates;
+use super::ignore_rules::{IgnoreMode, IgnoreRules};
+use super::path_filter::PathFilter;
+use super::scan_cache::{self, CacheEntry, FileSummary, ScanCache};
+use super::scan_tree::{NodeKind, PendingDir, PendingEntry, ScanNode};
+use super::top_files::TopFiles;
+use crossbeam::channel::{bounded, select, unbounded, Receiver, RecvTimeoutError, Sender};
+use dashmap::mapref::entry::Entry;
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering as CmpOrdering;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::os::unix::fs::DirEntryExt;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::ops::AddAssign;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time;
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum SizeMode {
+    #[default]
+    Apparent,
+    Disk,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, clap::ValueEnum)]
+pub enum HardLinkPolicy {
+    /// Count a hard-linked file once, where it is first seen
+    #[default]
+    First,
+    /// Count a hard-linked file in every location
+    All,
+    /// Split a hard-linked file's size evenly between its links
+    Split,
+}
+
+impl HardLinkPolicy {
+    /// Usage that one link to a file accounts for.
+    pub fn share(&self, metadata: &fs::Metadata, is_first: bool) -> Usage {
+        let usage = Usage::from_metadata(metadata);
+        let nlink = metadata.nlink();
+        if nlink <= 1 || metadata.is_dir() {
+            return usage;
+        }
+
+        match self {
+            HardLinkPolicy::All => usage,
+            HardLinkPolicy::First if is_first => usage,
+            HardLinkPolicy::First => Usage::default(),
+            HardLinkPolicy::Split => usage.split(nlink, is_first),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, clap::ValueEnum)]
+pub enum SortKey {
+    /// Size in the chosen size mode
+    #[default]
+    Size,
+    /// Entry name
+    Name,
+    /// Number of files
+    Count,
+    /// Last modification of the entry or of anything in it
+    Mtime,
+    /// Last access of the entry or of anything in it
+    Atime,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum)]
+pub enum SortOrder {
+    /// Smallest, fewest, oldest or A to Z first
+    Asc,
+    /// Largest, most, newest or Z to A first
+    Desc,
+}
+
+// What entries are compared on when sorting
+pub struct SortFields<'a> {
+    pub name: &'a str,
+    pub size: u64,
+    pub files: u64,
+    pub times: EntryTimes,
+}
+
+impl SortKey {
+    /// Names read best A to Z, everything else largest or newest first.
+    pub fn default_order(&self) -> SortOrder {
+        match self {
+            SortKey::Name => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        }
+    }
+
+    /// Ascending comparison on this key, falling back to the names.
+    pub fn compare(&self, a: SortFields, b: SortFields) -> CmpOrdering {
+        match self {
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Name => CmpOrdering::Equal,
+            SortKey::Count => a.files.cmp(&b.files),
+            SortKey::Mtime => a.times.mtime.cmp(&b.times.mtime),
+            SortKey::Atime => a.times.atime.cmp(&b.times.atime),
+        }
+        .then_with(|| a.name.cmp(b.name))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum ThreadCount {
+    #[default]
+    Default,
+    Auto,
+    Fixed(usize),
+}
+
+impl FromStr for ThreadCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(ThreadCount::Auto);
+        }
+
+        match s.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("expected a positive number or 'auto', got '{}'", s)),
+            Ok(n) => Ok(ThreadCount::Fixed(n)),
+        }
+    }
+}
+
+impl ThreadCount {
+    const MAX_AUTO_THREADS: usize = 64;
+    const TUNE_INTERVAL: time::Duration = time::Duration::from_millis(50);
+
+    /// Number of threads to start with. `Auto` adjusts it while scanning.
+    pub fn resolve(&self) -> usize {
+        match self {
+            ThreadCount::Default | ThreadCount::Auto => (num_cpus::get_physical() * 2 / 3).max(1),
+            ThreadCount::Fixed(n) => *n,
+        }
+    }
+
+    // A worker that spends a fraction `wait` of its time blocked on the file
+    // system leaves its core idle for that long, so it takes 1 / (1 - wait)
+    // workers per core to keep every core busy
+    fn tuned(busy_nanos: u64, io_nanos: u64) -> usize {
+        let compute_nanos = busy_nanos.saturating_sub(io_nanos).max(1);
+        let threads = num_cpus::get() as f64 * busy_nanos as f64 / compute_nanos as f64;
+        (threads.round() as usize).clamp(1, Self::MAX_AUTO_THREADS)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptions {
+    pub hard_links: HardLinkPolicy,
+    pub one_file_system: bool,
+    pub filter: PathFilter,
+    pub ignore_mode: IgnoreMode,
+    pub record_tree: bool,
+    pub use_cache: bool,
+    pub threads: ThreadCount,
+    // Remember the first link to every file rather than only to those with
+    // several, so that links added to a file later on still count once
+    pub track_links: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ScanStats {
+    pub hard_link_savings: AtomicUsage,
+    pub skipped_mounts: Mutex<Vec<String>>,
+    pub excluded: AtomicUsage,
+    pub errors: AtomicU64,
+    pub top_files: TopFiles,
+    pub duplicates: DupeCandidates,
+    pub hard_links: Arc<LinkClaims>,
+}
+
+/// Link through which each file with several links was counted first. Scans
+/// of part of an already scanned tree share it with the scan of the tree.
+#[derive(Debug, Default)]
+pub struct LinkClaims(DashMap<(u64, u64), PathBuf>);
+
+impl LinkClaims {
+    /// Whether `path` is the first link to the file, or already was the
+    /// last time it was counted. Files with a single link are only
+    /// remembered when `track_all` is set.
+    pub fn is_first(&self, metadata: &fs::Metadata, path: &Path, track_all: bool) -> bool {
+        if metadata.nlink() <= 1 && !track_all {
+            return true;
+        }
+
+        match self.0.entry((metadata.dev(), metadata.ino())) {
+            Entry::Vacant(entry) => {
+                entry.insert(path.to_path_buf());
+                true
+            }
+            Entry::Occupied(entry) => entry.get() == path,
+        }
+    }
+
+    /// Forgets the links counted at or below `path`. The other links to
+    /// their files keep their share until they're counted again.
+    pub fn release(&self, path: &Path) {
+        self.0.retain(|_, claimant| !claimant.starts_with(path));
+    }
+}
+
+/// What the walker knows about a directory by the time it gets there, to
+/// scan a directory of an already scanned tree on its own.
+#[derive(Debug, Clone)]
+pub struct WalkStart {
+    /// Device of the root of the tree, for `one_file_system`.
+    pub root_dev: Option<u64>,
+    /// Whether the directory or one above it matches an include glob.
+    pub included: bool,
+    /// Whether the directory or one above it is ignored.
+    pub ignored: bool,
+    /// Ignore rules in effect for the entries of the directory's parent.
+    pub ignore_rules: Option<Arc<IgnoreRules>>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub struct Usage {
+    pub apparent: u64,
+    pub disk: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        Self {
+            apparent: metadata.len(),
+            disk: metadata.blocks() * 512,
+            files: 1,
+        }
+    }
+
+    // One of `parts` shares. The first share also gets what doesn't divide
+    // evenly, so that all of them add up to the whole
+    fn split(&self, parts: u64, is_first: bool) -> Self {
+        let share = |size: u64| match is_first {
+            true => size / parts + size % parts,
+            false => size / parts,
+        };
+        Self {
+            apparent: share(self.apparent),
+            disk: share(self.disk),
+            files: self.files,
+        }
+    }
+
+    pub fn saturating_sub(&self, other: Usage) -> Self {
+        Self {
+            apparent: self.apparent.saturating_sub(other.apparent),
+            disk: self.disk.saturating_sub(other.disk),
+            files: self.files.saturating_sub(other.files),
+        }
+    }
+
+    pub fn get(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Apparent => self.apparent,
+            SizeMode::Disk => self.disk,
+        }
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.apparent += other.apparent;
+        self.disk += other.disk;
+        self.files += other.files;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AtomicUsage {
+    apparent: AtomicU64,
+    disk: AtomicU64,
+    files: AtomicU64,
+}
+
+impl AtomicUsage {
+    pub fn new(usage: Usage) -> Self {
+        let atomic_usage = Self::default();
+        atomic_usage.add(usage);
+        atomic_usage
+    }
+
+    fn add(&self, usage: Usage) {
+        self.apparent.fetch_add(usage.apparent, Ordering::Release);
+        self.disk.fetch_add(usage.disk, Ordering::Release);
+        self.files.fetch_add(usage.files, Ordering::Release);
+    }
+
+    pub fn load(&self) -> Usage {
+        Usage {
+            apparent: self.apparent.load(Ordering::Acquire),
+            disk: self.disk.load(Ordering::Acquire),
+            files: self.files.load(Ordering::Acquire),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub struct EntryTimes {
+    pub mtime: Option<i64>,
+    pub atime: Option<i64>,
+}
+
+impl EntryTimes {
+    pub fn from_metadata(metadata: Option<&fs::Metadata>) -> Self {
+        Self {
+            mtime: metadata.map(|m| m.mtime()),
+            atime: metadata.map(|m| m.atime()),
+        }
+    }
+
+    pub fn newest(self, other: EntryTimes) -> Self {
+        Self {
+            mtime: self.mtime.max(other.mtime),
+            atime: self.atime.max(other.atime),
+        }
+    }
+}
+
+// Newest times seen so far in a directory that's still being scanned
+#[derive(Debug)]
+pub struct AtomicTimes {
+    mtime: AtomicI64,
+    atime: AtomicI64,
+}
+
+impl Default for AtomicTimes {
+    fn default() -> Self {
+        Self {
+            mtime: AtomicI64::new(i64::MIN),
+            atime: AtomicI64::new(i64::MIN),
+        }
+    }
+}
+
+impl AtomicTimes {
+    pub fn new(times: EntryTimes) -> Self {
+        let atomic_times = Self::default();
+        atomic_times.update(times);
+        atomic_times
+    }
+
+    fn update(&self, times: EntryTimes) {
+        if let Some(mtime) = times.mtime {
+            self.mtime.fetch_max(mtime, Ordering::Release);
+        }
+        if let Some(atime) = times.atime {
+            self.atime.fetch_max(atime, Ordering::Release);
+        }
+    }
+
+    pub fn load(&self) -> EntryTimes {
+        let load = |time: &AtomicI64| Some(time.load(Ordering::Acquire)).filter(|&t| t != i64::MIN);
+        EntryTimes {
+            mtime: load(&self.mtime),
+            atime: load(&self.atime),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct DirectoryScanProgress {
     pub start_time: time::Instant,
     pub completed_time: Arc<Mutex<Option<time::Instant>>>,
-    pub size: Arc<AtomicU64>,
+    pub size: Arc<AtomicUsage>,
+    pub times: Arc<AtomicTimes>,
 }
 
 #[derive(Debug)]
 pub enum ItemView {
     Directory(String, DirectoryScanProgress),
-    File(String, u64),
+    File(String, Usage, EntryTimes),
+    Group(BreakdownGroup),
 }
 
-type ErrorHandler = dyn Fn(String) + Send + Sync + 'static;
+pub type ErrorHandler = dyn Fn(String) + Send + Sync + 'static;
 
-struct ChannelCtrl {
-    r: Receiver<Arc<ProcessMessage>>,
-    n_active_workers: u32,
+#[derive(Default)]
+struct Listing {
+    dirs: Vec<OsString>,
+    files: Vec<OsString>,
+}
+
+// How a file directly inside a directory was counted
+enum AddedFile {
+    Counted(Usage, EntryTimes),
+    Excluded(Usage),
+    // Counted once across all of its links
+    Linked,
+    Ignored,
 }
 
 struct Ctrl {
     s: Sender<Arc<ProcessMessage>>,
-    channel_ctrl: Mutex<ChannelCtrl>,
-    stop_flag: AtomicBool,
+    r: Receiver<Arc<ProcessMessage>>,
+    // Messages that were sent but haven't been processed yet
+    pending: AtomicUsize,
+    // Dropped once `pending` reaches zero, which wakes up all idle workers
+    done_s: Mutex<Option<Sender<()>>>,
+    done_r: Receiver<()>,
     on_error: Arc<ErrorHandler>,
-    visited: DashSet<u64>,
+    options: ScanOptions,
+    stats: Arc<ScanStats>,
+    root: String,
+    abs_root: Option<PathBuf>,
+    cache_options: u64,
+    root_dev: Option<u64>,
+    cache: Arc<ScanCache>,
+    // Directories walked so far, to walk bind mounts and loops only once
+    visited: DashSet<(u64, u64)>,
+    // Time spent processing messages and the part of it spent waiting on the
+    // file system, to tune the number of workers with `--threads auto`
+    busy_nanos: AtomicU64,
+    io_nanos: AtomicU64,
+    workers: AtomicUsize,
+    target_workers: AtomicUsize,
 }
 
 impl Ctrl {
-    fn get_message(&self) -> Option<Arc<ProcessMessage>> {
-        let mut channel_ctrl = self.channel_ctrl.lock().unwrap();
-        if let Ok(msg) = channel_ctrl.r.try_recv() {
-            channel_ctrl.n_active_workers += 1;
-            Some(msg)
-        } else {
-            None
+    fn send(&self, msg: Arc<ProcessMessage>) {
+        self.pending.fetch_add(1, Ordering::AcqRel);
+        self.s.send(msg).unwrap();
+    }
+
+    fn on_message_processed(&self) {
+        if self.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.done_s.lock().unwrap().take();
+        }
+    }
+
+    fn crosses_mount(&self, metadata: &fs::Metadata) -> bool {
+        self.options.one_file_system && self.root_dev.is_some_and(|dev| dev != metadata.dev())
+    }
+
+    fn first_visit(&self, metadata: &fs::Metadata) -> bool {
+        self.visited.insert((metadata.dev(), metadata.ino()))
+    }
+
+    fn hard_link_share(&self, metadata: &fs::Metadata, is_first: bool) -> Usage {
+        let usage = Usage::from_metadata(metadata);
+        let share = self.options.hard_links.share(metadata, is_first);
+        self.stats
+            .hard_link_savings
+            .add(usage.saturating_sub(share));
+        share
+    }
+
+    fn timed_io<T>(&self, io: impl FnOnce() -> T) -> T {
+        if self.options.threads != ThreadCount::Auto {
+            return io();
+        }
+
+        let start_time = time::Instant::now();
+        let result = io();
+        self.io_nanos
+            .fetch_add(start_time.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        result
+    }
+
+    // Leaves the pool if there are more workers than needed, keeping at least one
+    fn retire(&self) -> bool {
+        let target = self.target_workers.load(Ordering::Relaxed).max(1);
+        self.workers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |workers| {
+                (workers > target).then(|| workers - 1)
+            })
+            .is_ok()
+    }
+
+    fn spawn_workers<'scope>(ctrl: &Arc<Self>, scope: &'scope thread::Scope<'scope, '_>) {
+        let target = ctrl.target_workers.load(Ordering::Relaxed);
+        while ctrl.workers.load(Ordering::Acquire) < target {
+            ctrl.workers.fetch_add(1, Ordering::AcqRel);
+            let ctrl = ctrl.clone();
+            scope.spawn(move || Ctrl::work(ctrl));
         }
     }
 
-    fn on_message_processed(&self, msg: &Arc<ProcessMessage>) {
-        let mut channel_ctrl = msg.ctrl.channel_ctrl.lock().unwrap();
-        channel_ctrl.n_active_workers -= 1;
-        if channel_ctrl.n_active_workers == 0 && channel_ctrl.r.is_empty() {
-            msg.ctrl.stop_flag.store(true, Ordering::Release);
+    // Periodically resizes the pool to the share of time workers spent waiting
+    // on the file system since the last check, until the scan is done
+    fn tune<'scope>(ctrl: Arc<Self>, scope: &'scope thread::Scope<'scope, '_>) {
+        let (mut busy_nanos, mut io_nanos) = (0, 0);
+        while let Err(RecvTimeoutError::Timeout) =
+            ctrl.done_r.recv_timeout(ThreadCount::TUNE_INTERVAL)
+        {
+            let busy_total = ctrl.busy_nanos.load(Ordering::Relaxed);
+            let io_total = ctrl.io_nanos.load(Ordering::Relaxed);
+            if busy_total == busy_nanos {
+                continue;
+            }
+
+            let target = ThreadCount::tuned(busy_total - busy_nanos, io_total - io_nanos);
+            (busy_nanos, io_nanos) = (busy_total, io_total);
+            ctrl.target_workers.store(target, Ordering::Relaxed);
+            Ctrl::spawn_workers(&ctrl, scope);
         }
     }
 
     fn err(&self, msg: String) {
+        self.stats.errors.fetch_add(1, Ordering::Relaxed);
         let err_closure = &self.on_error;
         err_closure(msg);
     }
 
     fn work(ctrl: Arc<Self>) {
         loop {
-            match ctrl.get_message() {
-                Some(msg) => {
-                    ProcessMessage::process(&msg);
-                    ctrl.on_message_processed(&msg);
-                }
-                None => {
-                    if ctrl.stop_flag.load(Ordering::Acquire) {
-                        return;
+            select! {
+                recv(ctrl.r) -> msg => match msg {
+                    Ok(msg) => {
+                        let start_time = time::Instant::now();
+                        ProcessMessage::process(&msg);
+                        drop(msg);
+                        ctrl.busy_nanos
+                            .fetch_add(start_time.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                        ctrl.on_message_processed();
+                        if ctrl.retire() {
+                            return;
+                        }
                     }
-                }
+                    Err(_) => return,
+                },
+                recv(ctrl.done_r) -> _ => return,
             }
         }
     }
@@ -79,134 +539,379 @@ impl Ctrl {
 
 struct ProcessMessage {
     path: String,
-    size: Arc<AtomicU64>,
-    cache_ref: Arc<Mutex<HashMap<String, u64>>>,
+    size: Arc<AtomicUsage>,
+    times: Arc<AtomicTimes>,
+    metadata: Option<fs::Metadata>,
     parent: Option<Arc<ProcessMessage>>,
     ctrl: Arc<Ctrl>,
     render_children: Option<Arc<Mutex<Vec<Arc<ItemView>>>>>,
     view: Option<Arc<ItemView>>,
+    excluded: bool,
+    included: bool,
+    ignored: bool,
+    ignore_dir: Option<PathBuf>,
+    inherited_ignore_rules: Option<Arc<IgnoreRules>>,
+    tree_node: Option<Arc<PendingDir>>,
 }
 
 impl ProcessMessage {
     fn new(
         path: String,
-        size_cache: Arc<Mutex<HashMap<String, u64>>>,
         ctrl: Arc<Ctrl>,
         render_children: Option<Arc<Mutex<Vec<Arc<ItemView>>>>>,
     ) -> Self {
         Self {
             path,
-            size: Arc::new(AtomicU64::new(0)),
-            cache_ref: size_cache.clone(),
+            size: Arc::new(AtomicUsage::default()),
+            times: Arc::new(AtomicTimes::default()),
+            metadata: None,
             parent: None,
+            included: !ctrl.options.filter.has_includes(),
             ctrl,
             render_children,
             view: None,
+            excluded: false,
+            ignored: false,
+            ignore_dir: None,
+            inherited_ignore_rules: None,
+            tree_node: None,
         }
     }
 
     fn from_parent(parent: Arc<ProcessMessage>, child_path: String) -> Self {
+        let filter = &parent.ctrl.options.filter;
+        let excluded = parent.excluded || filter.is_excluded(Path::new(&child_path));
+        let included = parent.included || filter.is_included(Path::new(&child_path));
+        let ignored = parent.ignored;
         Self {
             path: child_path,
-            size: Arc::new(AtomicU64::new(0)),
-            cache_ref: parent.cache_ref.clone(),
+            size: Arc::new(AtomicUsage::default()),
+            times: Arc::new(AtomicTimes::default()),
+            metadata: None,
             ctrl: parent.ctrl.clone(),
             parent: Some(parent),
             render_children: None,
             view: None,
+            excluded,
+            included,
+            ignored,
+            ignore_dir: None,
+            inherited_ignore_rules: None,
+            tree_node: None,
         }
     }
 
-    fn add_size(&self, size: u64) {
-        self.size.fetch_add(size, Ordering::Release);
+    fn is_ignored(&self, rules: &Option<Arc<IgnoreRules>>, name: &OsStr, is_dir: bool) -> bool {
+        self.ignored || rules.as_ref().is_some_and(|r| r.is_ignored(name, is_dir))
+    }
+
+    fn add_size(&self, size: Usage) {
+        if self.excluded {
+            self.ctrl.stats.excluded.add(size);
+            return;
+        }
+
+        self.size.add(size);
 
         if let Some(parent) = &self.parent {
             parent.add_size(size);
         }
     }
 
-    fn traverse_path(msg: &Arc<Self>) {
-        if msg.path.starts_with("/dev/fd") {
+    fn add_times(&self, times: EntryTimes) {
+        if self.excluded {
             return;
         }
 
-        match fs::read_dir(&msg.path) {
-            Ok(entries) => {
-                let mut greedy_msg = None;
-                let entries = entries.filter_map(Result::ok);
-                let mut files = vec![];
-                for entry in entries {
-                    if let Ok(file_type) = entry.file_type() {
-                        let ino = entry.ino();
-                        if msg.ctrl.visited.contains(&ino) {
-                            continue;
-                        }
-                        msg.ctrl.visited.insert(ino);
-
-                        if file_type.is_dir() {
-                            let mut child_msg = ProcessMessage::from_parent(
-                                msg.clone(),
-                                entry.path().to_string_lossy().to_string(),
-                            );
-
-                            if let Some(render_children) = &msg.render_children {
-                                let render_line_item = Arc::new(ItemView::Directory(
-                                    entry.path().to_string_lossy().to_string(),
-                                    DirectoryScanProgress {
-                                        start_time: time::Instant::now(),
-                                        completed_time: Arc::new(Mutex::new(None)),
-                                        size: child_msg.size.clone(),
-                                    },
-                                ));
-                                child_msg.view = Some(render_line_item.clone());
-                                render_children.lock().unwrap().push(render_line_item);
-                            }
-
-                            let child_msg = Arc::from(child_msg);
-                            if greedy_msg.is_none() {
-                                greedy_msg = Some(child_msg);
-                            } else {
-                                msg.ctrl.s.send(child_msg).unwrap();
-                            }
-                        } else if file_type.is_file() || file_type.is_symlink() {
-                            files.push(entry);
-                        }
-                    }
-                }
+        self.times.update(times);
 
-                files.iter().for_each(|entry| {
-                    let file_size = entry.metadata().map(|m| m.len()).unwrap_or(0);
-                    msg.add_size(file_size);
+        if let Some(parent) = &self.parent {
+            parent.add_times(times);
+        }
+    }
 
-                    if let Some(render_children) = &msg.render_children {
-                        let mut render_children = render_children.lock().unwrap();
-                        render_children.push(Arc::new(ItemView::File(
-                            entry.path().to_string_lossy().to_string(),
-                            file_size,
-                        )));
-                    }
-                });
+    // Ignore rules can change without the directories they apply to changing
+    fn cache_key(&self) -> Option<PathBuf> {
+        if !self.ctrl.options.use_cache || self.ctrl.options.ignore_mode != IgnoreMode::Off {
+            return None;
+        }
 
-                if let Some(greedy_msg) = greedy_msg {
-                    ProcessMessage::process(&greedy_msg);
-                    return;
-                }
+        let abs_root = self.ctrl.abs_root.as_ref()?;
+        let relative = Path::new(&self.path).strip_prefix(&self.ctrl.root).ok()?;
+        match relative.as_os_str().is_empty() {
+            true => Some(abs_root.clone()),
+            false => Some(abs_root.join(relative)),
+        }
+    }
+
+    fn spawn_dir(
+        msg: &Arc<Self>,
+        path: PathBuf,
+        name: &OsStr,
+        ignore_rules: &Option<Arc<IgnoreRules>>,
+        greedy_msg: &mut Option<Arc<Self>>,
+    ) {
+        let metadata = match msg.ctrl.timed_io(|| fs::symlink_metadata(&path)) {
+            Ok(metadata) if msg.ctrl.crosses_mount(&metadata) => {
+                msg.ctrl
+                    .stats
+                    .skipped_mounts
+                    .lock()
+                    .unwrap()
+                    .push(path.to_string_lossy().to_string());
+                return;
             }
+            Ok(metadata) if !msg.ctrl.first_visit(&metadata) => return,
+            Ok(metadata) => metadata,
             Err(e) => {
-                msg.ctrl
-                    .err(format!("Error reading directory '{}': {}", &msg.path, e));
+                msg.ctrl.err(format!(
+                    "Error reading metadata of '{}': {}",
+                    path.display(),
+                    e
+                ));
+                return;
             }
         };
+
+        let ignored = msg.is_ignored(ignore_rules, name, true);
+        if ignored && msg.ctrl.options.ignore_mode == IgnoreMode::Respect {
+            return;
+        }
+
+        let mut child_msg =
+            ProcessMessage::from_parent(msg.clone(), path.to_string_lossy().to_string());
+        child_msg.ignored = ignored;
+        child_msg.add_times(EntryTimes::from_metadata(Some(&metadata)));
+        if let (Some(rules), false) = (ignore_rules, ignored) {
+            child_msg.ignore_dir = Some(rules.child_dir(name));
+            child_msg.inherited_ignore_rules = Some(rules.clone());
+        }
+
+        if let (Some(tree_node), false) = (&msg.tree_node, child_msg.excluded) {
+            let child_node = Arc::new(PendingDir::new(
+                name.to_string_lossy().to_string(),
+                Some(&metadata),
+                child_msg.size.clone(),
+                child_msg.times.clone(),
+            ));
+            tree_node.push(PendingEntry::Directory(child_node.clone()));
+            child_msg.tree_node = Some(child_node);
+        }
+
+        if let (Some(render_children), false) = (&msg.render_children, child_msg.excluded) {
+            let render_line_item = Arc::new(ItemView::Directory(
+                path.to_string_lossy().to_string(),
+                DirectoryScanProgress {
+                    start_time: time::Instant::now(),
+                    completed_time: Arc::new(Mutex::new(None)),
+                    size: child_msg.size.clone(),
+                    times: child_msg.times.clone(),
+                },
+            ));
+            child_msg.view = Some(render_line_item.clone());
+            render_children.lock().unwrap().push(render_line_item);
+        }
+
+        child_msg.metadata = Some(metadata);
+        let child_msg = Arc::from(child_msg);
+        if greedy_msg.is_none() {
+            *greedy_msg = Some(child_msg);
+        } else {
+            msg.ctrl.send(child_msg);
+        }
     }
 
-    fn process(msg: &Arc<Self>) {
-        if msg.render_children.is_none() {
-            if let Some(cache) = msg.cache_ref.lock().unwrap().get(&msg.path) {
-                msg.add_size(*cache);
-                return;
+    // Whether each file has to be looked at, rather than only their total
+    fn needs_each_file(&self) -> bool {
+        let stats = &self.ctrl.stats;
+        self.tree_node.is_some()
+            || self.render_children.is_some()
+            || stats.top_files.is_enabled()
+            || stats.duplicates.is_enabled()
+            || self.ctrl.options.track_links
+    }
+
+    fn list_dir(msg: &Arc<Self>) -> std::io::Result<Listing> {
+        let mut listing = Listing::default();
+        let entries = msg
+            .ctrl
+            .timed_io(|| fs::read_dir(&msg.path).map(|entries| entries.collect::<Vec<_>>()))?;
+        for entry in entries.into_iter().filter_map(Result::ok) {
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => listing.dirs.push(entry.file_name()),
+                Ok(file_type) if file_type.is_file() || file_type.is_symlink() => {
+                    listing.files.push(entry.file_name())
+                }
+                _ => {}
+            }
+        }
+        Ok(listing)
+    }
+
+    fn add_cached_files(&self, files: &FileSummary) {
+        self.ctrl.stats.excluded.add(files.excluded);
+        self.add_size(files.usage);
+        self.add_times(files.newest);
+    }
+
+    fn add_file(
+        msg: &Arc<Self>,
+        name: &OsStr,
+        ignore_rules: &Option<Arc<IgnoreRules>>,
+    ) -> AddedFile {
+        let ignored = msg.is_ignored(ignore_rules, name, false);
+        match msg.ctrl.options.ignore_mode {
+            IgnoreMode::Respect if ignored => return AddedFile::Ignored,
+            IgnoreMode::OnlyIgnored if !ignored => return AddedFile::Ignored,
+            _ => {}
+        }
+
+        let filter = &msg.ctrl.options.filter;
+        let path = Path::new(&msg.path).join(name);
+        let excluded = msg.excluded
+            || filter.is_excluded(&path)
+            || !(msg.included || filter.is_included(&path));
+        let metadata = msg.ctrl.timed_io(|| fs::symlink_metadata(&path)).ok();
+        // Excluded links mustn't claim the inode for `--hard-links first`
+        if excluded {
+            let excluded_size = metadata.as_ref().map(Usage::from_metadata);
+            msg.ctrl
+                .stats
+                .excluded
+                .add(excluded_size.unwrap_or_default());
+            return AddedFile::Excluded(excluded_size.unwrap_or_default());
+        }
+        let is_first_link = metadata.as_ref().is_some_and(|m| {
+            let track_all = msg.ctrl.options.track_links;
+            msg.ctrl.stats.hard_links.is_first(m, &path, track_all)
+        });
+        let file_size = metadata
+            .as_ref()
+            .map(|m| msg.ctrl.hard_link_share(m, is_first_link))
+            .unwrap_or_default();
+
+        let times = EntryTimes::from_metadata(metadata.as_ref());
+        msg.add_size(file_size);
+        msg.add_times(times);
+        msg.ctrl.stats.top_files.offer(
+            || path.to_string_lossy().to_string(),
+            file_size,
+            metadata.as_ref().map(|m| m.mtime()),
+        );
+        // A hard link shares its contents with the file rather than copying them
+        if let Some(metadata) = metadata.as_ref().filter(|_| is_first_link) {
+            msg.ctrl.stats.duplicates.offer(|| path.clone(), metadata);
+        }
+
+        if let Some(tree_node) = &msg.tree_node {
+            let kind = match metadata.as_ref().is_some_and(|m| m.is_symlink()) {
+                true => NodeKind::Symlink,
+                false => NodeKind::File,
+            };
+            tree_node.push(PendingEntry::File(Box::new(ScanNode::file(
+                name.to_string_lossy().to_string(),
+                kind,
+                file_size,
+                metadata.as_ref(),
+            ))));
+        }
+
+        if let Some(render_children) = &msg.render_children {
+            let mut render_children = render_children.lock().unwrap();
+            render_children.push(Arc::new(ItemView::File(
+                path.to_string_lossy().to_string(),
+                file_size,
+                times,
+            )));
+        }
+
+        match metadata.is_some_and(|m| m.nlink() > 1) {
+            true => AddedFile::Linked,
+            false => AddedFile::Counted(file_size, times),
+        }
+    }
+
+    // An unchanged directory that was cached is neither read again nor are its
+    // files looked at, unless each file is needed, apart from those with
+    // several links
+    fn traverse_path(msg: &Arc<Self>) {
+        if msg.path.starts_with("/dev/fd") {
+            return;
+        }
+
+        let cache_key = msg.cache_key();
+        let cached = match (&cache_key, &msg.metadata) {
+            (Some(key), Some(metadata)) if !msg.needs_each_file() => {
+                msg.ctrl.cache.lookup(key, metadata, msg.ctrl.cache_options)
             }
+            _ => None,
+        };
+        let listing = match &cached {
+            Some(entry) => Listing {
+                dirs: entry.dirs.iter().map(OsString::from).collect(),
+                files: entry.links.iter().map(OsString::from).collect(),
+            },
+            None => match ProcessMessage::list_dir(msg) {
+                Ok(listing) => listing,
+                Err(e) => {
+                    msg.ctrl
+                        .err(format!("Error reading directory '{}': {}", &msg.path, e));
+                    return;
+                }
+            },
+        };
+
+        let ignore_rules = msg.ignore_dir.as_ref().map(|dir| {
+            IgnoreRules::for_dir(dir.clone(), msg.inherited_ignore_rules.clone(), &|e| {
+                msg.ctrl.err(e)
+            })
+        });
+        let mut greedy_msg = None;
+        for name in &listing.dirs {
+            let path = Path::new(&msg.path).join(name);
+            ProcessMessage::spawn_dir(msg, path, name, &ignore_rules, &mut greedy_msg);
+        }
+        if let Some(entry) = &cached {
+            msg.add_cached_files(&entry.files);
         }
+        let mut files = FileSummary::default();
+        let mut links = Vec::new();
+        for name in &listing.files {
+            match ProcessMessage::add_file(msg, name, &ignore_rules) {
+                AddedFile::Counted(usage, times) => {
+                    files.usage += usage;
+                    files.newest = files.newest.newest(times);
+                }
+                AddedFile::Excluded(usage) => files.excluded += usage,
+                AddedFile::Linked => links.push(name),
+                AddedFile::Ignored => {}
+            }
+        }
+
+        // Names that aren't valid UTF-8 can't be stored
+        let names = |names: &[&OsString]| -> Option<Vec<String>> {
+            names
+                .iter()
+                .map(|name| name.to_str().map(String::from))
+                .collect()
+        };
+        if let (None, Some(key), Some(metadata), Some(dirs), Some(links)) = (
+            &cached,
+            &cache_key,
+            &msg.metadata,
+            names(&listing.dirs.iter().collect::<Vec<_>>()),
+            names(&links),
+        ) {
+            let entry = CacheEntry::new(metadata, msg.ctrl.cache_options, dirs, links, files);
+            msg.ctrl.cache.record(key, entry);
+        }
+
+        if let Some(greedy_msg) = greedy_msg {
+            ProcessMessage::process(&greedy_msg);
+        }
+    }
 
+    fn process(msg: &Arc<Self>) {
         ProcessMessage::traverse_path(msg);
     }
 }
@@ -222,46 +927,386 @@ impl Drop for ProcessMessage {
                     .replace(time::Instant::now());
             }
         }
-
-        let size = self.size.load(Ordering::Acquire);
-        if size > 1_000_000_000 {
-            self.cache_ref
-                .lock()
-                .unwrap()
-                .insert(self.path.clone(), size);
-        }
     }
 }
 
 pub fn get_dir_size(
     root: &str,
-    size_cache: Arc<Mutex<HashMap<String, u64>>>,
-    render_view: Arc<Mutex<Vec<Arc<ItemView>>>>,
+    options: ScanOptions,
+    stats: Arc<ScanStats>,
+    cache: Arc<ScanCache>,
+    render_view: Option<Arc<Mutex<Vec<Arc<ItemView>>>>>,
+    on_error: Arc<ErrorHandler>,
+) -> Option<ScanNode> {
+    walk(root, options, stats, cache, render_view, on_error, None)
+}
+
+/// Scans `path` the way the walker would have as part of a larger scan.
+pub fn get_subtree_size(
+    path: &str,
+    options: ScanOptions,
+    stats: Arc<ScanStats>,
+    start: WalkStart,
     on_error: Arc<ErrorHandler>,
-) {
+) -> Option<ScanNode> {
+    let cache = Arc::new(ScanCache::in_memory());
+    walk(path, options, stats, cache, None, on_error, Some(start))
+}
+
+fn walk(
+    root: &str,
+    options: ScanOptions,
+    stats: Arc<ScanStats>,
+    cache: Arc<ScanCache>,
+    render_view: Option<Arc<Mutex<Vec<Arc<ItemView>>>>>,
+    on_error: Arc<ErrorHandler>,
+    start: Option<WalkStart>,
+) -> Option<ScanNode> {
+    let root_metadata = fs::metadata(root).ok();
+    let abs_root = match options.use_cache {
+        true => Path::new(root).canonicalize().ok(),
+        false => None,
+    };
+    if let Some(abs_root) = &abs_root {
+        cache.add_root(abs_root);
+    }
+    let initial_workers = options.threads.resolve();
+    let cache_options = scan_cache::options_key(root, &options.filter);
     let (dir_s, dir_r) = unbounded::<Arc<ProcessMessage>>();
+    let (done_s, done_r) = bounded::<()>(0);
     let ctrl = Arc::new(Ctrl {
-        s: dir_s.clone(),
-        channel_ctrl: Mutex::new(ChannelCtrl {
-            r: dir_r.clone(),
-            n_active_workers: 0,
-        }),
-        stop_flag: AtomicBool::new(false),
+        s: dir_s,
+        r: dir_r,
+        pending: AtomicUsize::new(0),
+        done_s: Mutex::new(Some(done_s)),
+        done_r,
         on_error,
+        options,
+        stats,
+        root: root.to_string(),
+        abs_root,
+        cache_options,
+        root_dev: match &start {
+            Some(start) => start.root_dev,
+            None => root_metadata.as_ref().map(|m| m.dev()),
+        },
+        cache,
         visited: DashSet::new(),
+        busy_nanos: AtomicU64::new(0),
+        io_nanos: AtomicU64::new(0),
+        workers: AtomicUsize::new(0),
+        target_workers: AtomicUsize::new(initial_workers),
     });
-    let root_msg = Arc::new(ProcessMessage::new(
-        root.to_string(),
-        size_cache.clone(),
-        ctrl.clone(),
-        Some(render_view),
-    ));
-
-    dir_s.send(root_msg.clone()).unwrap();
+    let mut root_msg = ProcessMessage::new(root.to_string(), ctrl.clone(), render_view);
+    root_msg.metadata = root_metadata.clone();
+    root_msg.add_times(EntryTimes::from_metadata(root_metadata.as_ref()));
+    if ctrl.options.record_tree {
+        root_msg.tree_node = Some(Arc::new(PendingDir::new(
+            root.to_string(),
+            root_metadata.as_ref(),
+            root_msg.size.clone(),
+            root_msg.times.clone(),
+        )));
+    }
+    if let Some(start) = &start {
+        root_msg.included |= start.included;
+        root_msg.ignored = start.ignored;
+    }
+    if ctrl.options.ignore_mode != IgnoreMode::Off && !root_msg.ignored {
+        root_msg.ignore_dir = Path::new(root).canonicalize().ok();
+        root_msg.inherited_ignore_rules = match &start {
+            Some(start) => start.ignore_rules.clone(),
+            None => IgnoreRules::for_ancestors(Path::new(root), &|e| ctrl.err(e)),
+        };
+    }
+    let root_msg = Arc::new(root_msg);
+
+    if let Some(metadata) = &root_metadata {
+        ctrl.first_visit(metadata);
+    }
+
+    ctrl.send(root_msg.clone());
     thread::scope(|scope| {
-        for _ in 0..(num_cpus::get_physical() * 2 / 3).max(1) {
+        Ctrl::spawn_workers(&ctrl, scope);
+        if ctrl.options.threads == ThreadCount::Auto {
             let ctrl = ctrl.clone();
-            scope.spawn(move || Ctrl::work(ctrl));
+            scope.spawn(move || Ctrl::tune(ctrl, scope));
         }
     });
+
+    root_msg.tree_node.as_ref().map(|node| node.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn scan(root: &Path, options: ScanOptions) -> Usage {
+        scan_tree(root, options, Arc::new(ScanCache::in_memory())).usage
+    }
+
+    // Adds up the items a chart would show, without recording a tree
+    fn scan_cached(root: &Path, options: ScanOptions, cache: Arc<ScanCache>) -> Usage {
+        let items = Arc::new(Mutex::new(Vec::new()));
+        let stats = Arc::new(ScanStats::default());
+        let on_error = Arc::new(|e| panic!("{}", e));
+        get_dir_size(
+            root.to_str().unwrap(),
+            options,
+            stats,
+            cache,
+            Some(items.clone()),
+            on_error,
+        );
+
+        let mut usage = Usage::default();
+        for item in items.lock().unwrap().iter() {
+            usage += match item.as_ref() {
+                ItemView::Directory(_, progress) => progress.size.load(),
+                ItemView::File(_, size, _) => *size,
+                ItemView::Group(_) => Usage::default(),
+            };
+        }
+        usage
+    }
+
+    fn scan_tree(root: &Path, options: ScanOptions, cache: Arc<ScanCache>) -> ScanNode {
+        let stats = Arc::new(ScanStats::default());
+        let tree = get_dir_size(
+            root.to_str().unwrap(),
+            ScanOptions {
+                record_tree: true,
+                ..options
+            },
+            stats,
+            cache,
+            None,
+            Arc::new(|e| panic!("{}", e)),
+        );
+        tree.unwrap()
+    }
+
+    fn hard_links(policy: HardLinkPolicy) -> ScanOptions {
+        ScanOptions {
+            hard_links: policy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn split_shares_add_up_to_the_whole() {
+        let usage = Usage {
+            apparent: 10,
+            disk: 4096,
+            files: 1,
+        };
+        let first = usage.split(3, true);
+        let rest = usage.split(3, false);
+        assert_eq!(first.apparent + 2 * rest.apparent, 10);
+        assert_eq!(first.disk + 2 * rest.disk, 4096);
+        assert_eq!(rest.apparent, 3);
+        assert_eq!(first.files, 1);
+    }
+
+    #[test]
+    fn hard_link_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a")).unwrap();
+        fs::create_dir_all(dir.path().join("b")).unwrap();
+        fs::write(dir.path().join("a/f"), vec![0; 1001]).unwrap();
+        fs::hard_link(dir.path().join("a/f"), dir.path().join("b/f")).unwrap();
+        fs::hard_link(dir.path().join("a/f"), dir.path().join("b/g")).unwrap();
+
+        let first = scan(dir.path(), hard_links(HardLinkPolicy::First));
+        assert_eq!(first.apparent, 1001);
+        let all = scan(dir.path(), hard_links(HardLinkPolicy::All));
+        assert_eq!(all.apparent, 3003);
+        let split = scan(dir.path(), hard_links(HardLinkPolicy::Split));
+        assert_eq!(split.apparent, 1001);
+        assert_eq!(split.disk, first.disk);
+    }
+
+    #[test]
+    fn excluded_hard_links_dont_hide_included_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a")).unwrap();
+        fs::create_dir_all(dir.path().join("b")).unwrap();
+        fs::write(dir.path().join("a/big"), vec![0; 1000]).unwrap();
+        fs::hard_link(dir.path().join("a/big"), dir.path().join("b/big")).unwrap();
+
+        // Directories are read in no particular order, so exclude either one
+        for excluded in ["**/a", "**/b"] {
+            let options = ScanOptions {
+                filter: PathFilter::new(&[excluded.to_string()], &[], &[]).unwrap(),
+                ..Default::default()
+            };
+            assert_eq!(scan(dir.path(), options).apparent, 1000, "{}", excluded);
+        }
+    }
+
+    #[test]
+    fn cached_directories_keep_file_sizes_until_they_change() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sub/deep")).unwrap();
+        fs::write(dir.path().join("sub/deep/f"), "abc").unwrap();
+        let cache = Arc::new(ScanCache::in_memory());
+        let options = ScanOptions {
+            use_cache: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            scan_cached(dir.path(), options.clone(), cache.clone()).apparent,
+            3
+        );
+        // Growing a file in place doesn't change its directory's mtime
+        fs::write(dir.path().join("sub/deep/f"), vec![0; 5000]).unwrap();
+        assert_eq!(
+            scan_cached(dir.path(), options.clone(), cache.clone()).apparent,
+            3
+        );
+        let uncached = ScanOptions {
+            use_cache: false,
+            ..options.clone()
+        };
+        assert_eq!(
+            scan_cached(dir.path(), uncached, cache.clone()).apparent,
+            5000
+        );
+
+        fs::write(dir.path().join("sub/deep/g"), "g").unwrap();
+        assert_eq!(scan_cached(dir.path(), options, cache).apparent, 5001);
+    }
+
+    #[test]
+    fn cached_directories_still_count_hard_links_once() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a")).unwrap();
+        fs::create_dir_all(dir.path().join("b")).unwrap();
+        fs::write(dir.path().join("a/f"), vec![0; 1000]).unwrap();
+        fs::hard_link(dir.path().join("a/f"), dir.path().join("b/f")).unwrap();
+        let cache = Arc::new(ScanCache::in_memory());
+
+        for policy in [
+            HardLinkPolicy::First,
+            HardLinkPolicy::All,
+            HardLinkPolicy::First,
+        ] {
+            let options = ScanOptions {
+                use_cache: true,
+                ..hard_links(policy)
+            };
+            let expected = match policy {
+                HardLinkPolicy::All => 2000,
+                _ => 1000,
+            };
+            let usage = scan_cached(dir.path(), options, cache.clone());
+            assert_eq!(usage.apparent, expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn parses_thread_counts() {
+        assert_eq!("auto".parse(), Ok(ThreadCount::Auto));
+        assert_eq!("AUTO".parse(), Ok(ThreadCount::Auto));
+        assert_eq!("8".parse(), Ok(ThreadCount::Fixed(8)));
+        assert!("0".parse::<ThreadCount>().is_err());
+        assert!("-1".parse::<ThreadCount>().is_err());
+        assert!("many".parse::<ThreadCount>().is_err());
+    }
+
+    #[test]
+    fn tunes_workers_to_io_wait() {
+        let cpus = num_cpus::get();
+        assert_eq!(
+            ThreadCount::tuned(1000, 0),
+            cpus.min(ThreadCount::MAX_AUTO_THREADS)
+        );
+        assert_eq!(
+            ThreadCount::tuned(1000, 500),
+            (cpus * 2).min(ThreadCount::MAX_AUTO_THREADS)
+        );
+        assert_eq!(
+            ThreadCount::tuned(1000, 1000),
+            ThreadCount::MAX_AUTO_THREADS
+        );
+    }
+
+    #[test]
+    fn auto_threads_scan_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..50 {
+            fs::create_dir_all(dir.path().join(format!("{}/{}", i % 7, i))).unwrap();
+            fs::write(dir.path().join(format!("{}/{}/f", i % 7, i)), "x").unwrap();
+        }
+        let options = ScanOptions {
+            threads: ThreadCount::Auto,
+            ..Default::default()
+        };
+        assert_eq!(scan(dir.path(), options).files, 50);
+    }
+
+    fn fields(name: &str, size: u64, files: u64, mtime: Option<i64>) -> SortFields<'_> {
+        SortFields {
+            name,
+            size,
+            files,
+            times: EntryTimes { mtime, atime: None },
+        }
+    }
+
+    #[test]
+    fn sort_keys_compare_their_field() {
+        let a = || fields("a", 10, 5, Some(100));
+        let b = || fields("b", 20, 1, None);
+        assert_eq!(SortKey::Size.compare(a(), b()), CmpOrdering::Less);
+        assert_eq!(SortKey::Name.compare(a(), b()), CmpOrdering::Less);
+        assert_eq!(SortKey::Count.compare(a(), b()), CmpOrdering::Greater);
+        // Entries without a time come before any with one
+        assert_eq!(SortKey::Mtime.compare(a(), b()), CmpOrdering::Greater);
+        assert_eq!(SortKey::Atime.compare(a(), b()), CmpOrdering::Less);
+
+        // Ties fall back to the names
+        let c = fields("c", 10, 5, Some(100));
+        assert_eq!(SortKey::Size.compare(c, a()), CmpOrdering::Greater);
+        assert_eq!(SortKey::Name.compare(a(), a()), CmpOrdering::Equal);
+    }
+
+    #[test]
+    fn directories_take_the_newest_times_inside() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("old")).unwrap();
+        fs::create_dir_all(dir.path().join("new/deep")).unwrap();
+        fs::write(dir.path().join("old/a"), "a").unwrap();
+        fs::write(dir.path().join("new/deep/b"), "b").unwrap();
+        let set_mtime = |path: &str, secs: u64| {
+            let mtime = time::UNIX_EPOCH + time::Duration::from_secs(secs);
+            let file = fs::File::open(dir.path().join(path)).unwrap();
+            file.set_modified(mtime).unwrap();
+        };
+        set_mtime("old/a", 2000);
+        set_mtime("new/deep/b", 3000);
+        for path in ["old", "new/deep", "new", "."] {
+            set_mtime(path, 1000);
+        }
+
+        let tree = scan_tree(
+            dir.path(),
+            ScanOptions::default(),
+            Arc::new(ScanCache::in_memory()),
+        );
+        let newest = |path: &str| {
+            tree.find(&Path::new(&tree.name).join(path))
+                .unwrap()
+                .newest
+                .mtime
+        };
+        assert_eq!(newest("."), Some(3000));
+        assert_eq!(newest("old"), Some(2000));
+        assert_eq!(newest("new"), Some(3000));
+        assert_eq!(newest("new/deep"), Some(3000));
+        // The entry's own time is kept as it is
+        assert_eq!(tree.mtime, Some(1000));
+    }
 }
@@ -0,0 +1,148 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum IgnoreMode {
+    #[default]
+    Off,
+    Respect,
+    OnlyIgnored,
+}
+
+// Ignore files of a single directory, in order of precedence
+const IGNORE_FILES: [&str; 3] = [".ignore", ".gitignore", ".git/info/exclude"];
+
+#[derive(Debug)]
+pub struct IgnoreRules {
+    dir: PathBuf,
+    matchers: Vec<Gitignore>,
+    parent: Option<Arc<IgnoreRules>>,
+}
+
+impl IgnoreRules {
+    /// Rules inherited by `root` from the enclosing repository, if any.
+    pub fn for_ancestors(root: &Path, on_error: &dyn Fn(String)) -> Option<Arc<Self>> {
+        let root = root.canonicalize().ok()?;
+        let repo_root = root
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.join(".git").exists())?;
+
+        let mut dirs = root
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(repo_root))
+            .collect::<Vec<_>>();
+        dirs.reverse();
+
+        dirs.into_iter().fold(None, |parent, dir| {
+            Some(Self::for_dir(dir.to_path_buf(), parent, on_error))
+        })
+    }
+
+    /// Rules in effect for the entries of `dir`. `dir` must be absolute.
+    pub fn for_dir(
+        dir: PathBuf,
+        parent: Option<Arc<Self>>,
+        on_error: &dyn Fn(String),
+    ) -> Arc<Self> {
+        // Rules from outside of a repository don't apply inside of it
+        let parent = match dir.join(".git").exists() {
+            true => None,
+            false => parent,
+        };
+
+        let matchers = IGNORE_FILES
+            .iter()
+            .map(|file| dir.join(file))
+            .filter(|path| path.is_file())
+            .filter_map(|path| {
+                let mut builder = GitignoreBuilder::new(&dir);
+                if let Some(e) = builder.add(&path) {
+                    on_error(format!("Error reading '{}': {}", path.display(), e));
+                }
+                builder.build().ok()
+            })
+            .collect();
+
+        Arc::new(Self {
+            dir,
+            matchers,
+            parent,
+        })
+    }
+
+    pub fn child_dir(&self, name: &OsStr) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn is_ignored(&self, name: &OsStr, is_dir: bool) -> bool {
+        let path = self.dir.join(name);
+        let mut rules = Some(self);
+        while let Some(current) = rules {
+            for matcher in &current.matchers {
+                match matcher.matched(&path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+            rules = current.parent.as_deref();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn no_errors(e: String) {
+        panic!("{}", e)
+    }
+
+    #[test]
+    fn nested_rules_override_their_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\ntarget/\n").unwrap();
+        fs::write(root.join(".git/info/exclude"), "secret\n").unwrap();
+        fs::write(root.join("src/.ignore"), "!keep.log\n").unwrap();
+
+        let rules = IgnoreRules::for_dir(root.clone(), None, &no_errors);
+        assert!(rules.is_ignored(OsStr::new("a.log"), false));
+        assert!(rules.is_ignored(OsStr::new("target"), true));
+        assert!(!rules.is_ignored(OsStr::new("target"), false));
+        assert!(rules.is_ignored(OsStr::new("secret"), false));
+        assert!(!rules.is_ignored(OsStr::new("main.rs"), false));
+
+        let src = IgnoreRules::for_dir(rules.child_dir(OsStr::new("src")), Some(rules), &no_errors);
+        assert!(src.is_ignored(OsStr::new("debug.log"), false));
+        assert!(!src.is_ignored(OsStr::new("keep.log"), false));
+    }
+
+    #[test]
+    fn inherits_rules_from_the_enclosing_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join(".gitignore"), "*.tmp\n").unwrap();
+
+        let rules = IgnoreRules::for_ancestors(&root.join("a/b"), &no_errors).unwrap();
+        let rules = IgnoreRules::for_dir(root.join("a/b"), Some(rules), &no_errors);
+        assert!(rules.is_ignored(OsStr::new("x.tmp"), false));
+
+        // A nested repository starts over
+        fs::create_dir_all(root.join("a/b/.git")).unwrap();
+        let rules = IgnoreRules::for_ancestors(&root.join("a/b"), &no_errors);
+        let rules = IgnoreRules::for_dir(root.join("a/b"), rules, &no_errors);
+        assert!(!rules.is_ignored(OsStr::new("x.tmp"), false));
+    }
+}
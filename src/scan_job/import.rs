@@ -0,0 +1,417 @@
+use super::export::{ScanMetadata, SCHEMA_VERSION};
+use super::file_util::{EntryTimes, Usage};
+use super::scan_tree::{FileStat, NodeKind, ScanNode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct JsonDump {
+    schema_version: u32,
+    scan: ScanMetadata,
+    tree: JsonNode,
+}
+
+#[derive(Deserialize)]
+struct JsonNode {
+    path: String,
+    #[serde(rename = "type")]
+    kind: NodeKind,
+    apparent_size: u64,
+    disk_size: u64,
+    file_count: u64,
+    mtime: Option<i64>,
+    // Missing from dumps written before access times and owners were exported
+    atime: Option<i64>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    // Only hard links have these
+    dev: Option<u64>,
+    ino: Option<u64>,
+    nlink: Option<u64>,
+    #[serde(default)]
+    children: Vec<JsonNode>,
+}
+
+impl JsonNode {
+    // Files hold their own sizes, so a hard-linked file is counted where it's
+    // first seen, and directories add up what's counted in them
+    fn into_scan_node(self, name: String, hard_links: &mut HashSet<(u64, u64)>) -> ScanNode {
+        let size = Usage {
+            apparent: self.apparent_size,
+            disk: self.disk_size,
+            files: 1,
+        };
+        let stat = FileStat {
+            size,
+            dev: self.dev.unwrap_or(0),
+            ino: self.ino.unwrap_or(0),
+            nlink: self.nlink.unwrap_or(1),
+        };
+        let mut node = ScanNode {
+            name,
+            kind: self.kind,
+            usage: Usage {
+                files: self.file_count,
+                ..size
+            },
+            mtime: self.mtime,
+            atime: self.atime,
+            uid: self.uid,
+            gid: self.gid,
+            newest: EntryTimes::default(),
+            stat: None,
+            children: self
+                .children
+                .into_iter()
+                .map(|child| {
+                    let name = Path::new(&child.path)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_else(|| child.path.clone());
+                    child.into_scan_node(name, hard_links)
+                })
+                .collect(),
+        };
+        match node.kind {
+            NodeKind::Directory => {
+                node.usage = Usage::default();
+                for child in &node.children {
+                    node.usage += child.usage;
+                }
+            }
+            NodeKind::File | NodeKind::Symlink => {
+                if stat.is_hard_link() && !hard_links.insert((stat.dev, stat.ino)) {
+                    node.usage = Usage::default();
+                }
+                node.stat = Some(stat);
+            }
+        }
+        node.refresh_newest();
+        node
+    }
+}
+
+pub fn load_dump(path: &Path) -> anyhow::Result<(ScanMetadata, ScanNode)> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Error opening '{}': {}", path.display(), e))?;
+    let value: Value = serde_json::from_reader(BufReader::new(file))?;
+
+    match value {
+        Value::Array(_) => load_ncdu(value),
+        _ => load_json(value),
+    }
+}
+
+fn load_json(value: Value) -> anyhow::Result<(ScanMetadata, ScanNode)> {
+    let dump: JsonDump = serde_json::from_value(value)?;
+    if dump.schema_version > SCHEMA_VERSION {
+        anyhow::bail!(
+            "Unsupported schema version {} (expected at most {})",
+            dump.schema_version,
+            SCHEMA_VERSION
+        );
+    }
+
+    let name = dump.tree.path.clone();
+    let tree = dump.tree.into_scan_node(name, &mut HashSet::new());
+    Ok((dump.scan, tree))
+}
+
+fn load_ncdu(value: Value) -> anyhow::Result<(ScanMetadata, ScanNode)> {
+    let items = match value {
+        Value::Array(items) if items.len() >= 4 => items,
+        _ => anyhow::bail!("Not an ncdu export: expected [major, minor, header, root]"),
+    };
+    if items[0].as_u64() != Some(1) {
+        anyhow::bail!("Unsupported ncdu export version {}", items[0]);
+    }
+
+    let mut loader = NcduLoader::default();
+    let tree = loader
+        .node(&items[3], 0)?
+        .ok_or_else(|| anyhow::anyhow!("The root of the ncdu export is excluded"))?;
+    let metadata = ScanMetadata {
+        root: tree.name.clone(),
+        started_at: items[2]["timestamp"].as_u64().unwrap_or(0),
+        duration_ms: 0,
+        error_count: loader.errors,
+    };
+    Ok((metadata, tree))
+}
+
+#[derive(Default)]
+struct NcduLoader {
+    hard_links: HashSet<(u64, u64)>,
+    errors: u64,
+}
+
+impl NcduLoader {
+    // Entries only list their device when it differs from their parent's.
+    // Excluded entries and other file systems weren't scanned, so they're left out
+    fn node(&mut self, value: &Value, parent_dev: u64) -> anyhow::Result<Option<ScanNode>> {
+        match value {
+            Value::Array(items) => {
+                let info = items
+                    .first()
+                    .ok_or_else(|| anyhow::anyhow!("Directory entry without info"))?;
+                if is_excluded(info) {
+                    return Ok(None);
+                }
+                let dev = info["dev"].as_u64().unwrap_or(parent_dev);
+                let children = items[1..]
+                    .iter()
+                    .map(|child| self.node(child, dev))
+                    .collect::<anyhow::Result<Vec<_>>>()?
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+                let mut node = self.info(info, NodeKind::Directory)?;
+                node.usage.files = 0;
+                for child in &children {
+                    node.usage.apparent += child.usage.apparent;
+                    node.usage.disk += child.usage.disk;
+                    node.usage.files += child.usage.files;
+                }
+                node.children = children;
+                node.refresh_newest();
+                Ok(Some(node))
+            }
+            Value::Object(_) if is_excluded(value) => Ok(None),
+            Value::Object(_) => {
+                let kind = match value["notreg"].as_bool() {
+                    Some(true) => NodeKind::Symlink,
+                    _ => NodeKind::File,
+                };
+                let mut node = self.info(value, kind)?;
+                let is_hard_link = value["hlnkc"].as_bool() == Some(true)
+                    || value["nlink"].as_u64().is_some_and(|nlink| nlink > 1);
+                let stat = FileStat {
+                    size: node.usage,
+                    dev: value["dev"].as_u64().unwrap_or(parent_dev),
+                    ino: value["ino"].as_u64().unwrap_or(0),
+                    nlink: match value["nlink"].as_u64() {
+                        Some(nlink) => nlink,
+                        None if is_hard_link => 2,
+                        None => 1,
+                    },
+                };
+                if is_hard_link && !self.hard_links.insert((stat.dev, stat.ino)) {
+                    node.usage = Usage::default();
+                }
+                node.stat = Some(stat);
+                Ok(Some(node))
+            }
+            _ => anyhow::bail!("Unexpected ncdu entry: {}", value),
+        }
+    }
+
+    fn info(&mut self, info: &Value, kind: NodeKind) -> anyhow::Result<ScanNode> {
+        let name = info["name"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Entry without a name: {}", info))?;
+        if info["read_error"].as_bool() == Some(true) {
+            self.errors += 1;
+        }
+
+        let times = EntryTimes {
+            mtime: info["mtime"].as_i64(),
+            atime: info["atime"].as_i64(),
+        };
+        Ok(ScanNode {
+            name: name.to_string(),
+            kind,
+            usage: Usage {
+                apparent: info["asize"].as_u64().unwrap_or(0),
+                disk: info["dsize"].as_u64().unwrap_or(0),
+                files: 1,
+            },
+            mtime: times.mtime,
+            atime: times.atime,
+            uid: info["uid"].as_u64().map(|uid| uid as u32),
+            gid: info["gid"].as_u64().map(|gid| gid as u32),
+            newest: times,
+            stat: None,
+            children: Vec::new(),
+        })
+    }
+}
+
+fn is_excluded(info: &Value) -> bool {
+    !info["excluded"].is_null()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_job::export::{write_json, write_ncdu};
+    use crate::scan_job::scan_tree::{dir, file};
+    use serde_json::json;
+
+    #[test]
+    fn ncdu_round_trip() {
+        let tree = dir(
+            "/df2/root",
+            vec![dir("sub", vec![file("a", 10), file("b", 20)]), file("c", 5)],
+        );
+        let metadata = ScanMetadata {
+            root: tree.name.clone(),
+            started_at: 1700000000,
+            duration_ms: 0,
+            error_count: 0,
+        };
+        let mut dump = Vec::new();
+        write_ncdu(&mut dump, &metadata, &tree).unwrap();
+
+        let (loaded_metadata, loaded) = load_ncdu(serde_json::from_slice(&dump).unwrap()).unwrap();
+        assert_eq!(loaded_metadata.started_at, 1700000000);
+        assert_eq!(loaded.name, "/df2/root");
+        assert_eq!(loaded.usage, tree.usage);
+        let sub = &loaded.children[0];
+        assert_eq!((sub.name.as_str(), sub.kind), ("sub", NodeKind::Directory));
+        assert_eq!(sub.usage.apparent, 30);
+        assert_eq!(sub.children.len(), 2);
+    }
+
+    #[test]
+    fn round_trips_keep_owners() {
+        let mut owned = file("a", 10);
+        owned.uid = Some(1000);
+        owned.gid = Some(100);
+        let tree = dir("/df2/root", vec![owned]);
+        let metadata = ScanMetadata {
+            root: tree.name.clone(),
+            started_at: 0,
+            duration_ms: 0,
+            error_count: 0,
+        };
+
+        let mut dump = Vec::new();
+        write_json(&mut dump, &metadata, &tree).unwrap();
+        let (_, loaded) = load_json(serde_json::from_slice(&dump).unwrap()).unwrap();
+        let owner = |node: &ScanNode| (node.children[0].uid, node.children[0].gid);
+        assert_eq!(owner(&loaded), (Some(1000), Some(100)));
+
+        let mut dump = Vec::new();
+        write_ncdu(&mut dump, &metadata, &tree).unwrap();
+        let (_, loaded) = load_ncdu(serde_json::from_slice(&dump).unwrap()).unwrap();
+        assert_eq!(owner(&loaded), (Some(1000), Some(100)));
+    }
+
+    #[test]
+    fn round_trips_keep_access_times() {
+        let accessed = ScanNode {
+            atime: Some(1700000000),
+            ..file("a", 10)
+        };
+        let tree = dir("/df2/root", vec![accessed]);
+        let metadata = ScanMetadata {
+            root: tree.name.clone(),
+            started_at: 0,
+            duration_ms: 0,
+            error_count: 0,
+        };
+
+        let mut dump = Vec::new();
+        write_json(&mut dump, &metadata, &tree).unwrap();
+        let (_, loaded) = load_json(serde_json::from_slice(&dump).unwrap()).unwrap();
+        assert_eq!(loaded.children[0].atime, Some(1700000000));
+        assert_eq!(loaded.newest.atime, Some(1700000000));
+
+        let mut dump = Vec::new();
+        write_ncdu(&mut dump, &metadata, &tree).unwrap();
+        let (_, loaded) = load_ncdu(serde_json::from_slice(&dump).unwrap()).unwrap();
+        assert_eq!(loaded.newest.atime, Some(1700000000));
+
+        // Dumps without access times
+        let dump = json!([1, 2, {}, [{"name": "/root"}, {"name": "a", "mtime": 5}]]);
+        let (_, loaded) = load_ncdu(dump).unwrap();
+        assert_eq!(loaded.newest.atime, None);
+    }
+
+    #[test]
+    fn round_trips_count_hard_links_once() {
+        let stat = FileStat {
+            size: file("x", 100).usage,
+            dev: 1,
+            ino: 7,
+            nlink: 2,
+        };
+        let link = |name: &str, usage: Usage| ScanNode {
+            usage,
+            stat: Some(stat),
+            ..file(name, 0)
+        };
+        // Counted in full where it was first seen, and not at all elsewhere
+        let tree = dir(
+            "/df2/root",
+            vec![
+                dir("a", vec![link("x", stat.size)]),
+                dir("b", vec![link("y", Usage::default())]),
+            ],
+        );
+        let metadata = ScanMetadata {
+            root: tree.name.clone(),
+            started_at: 0,
+            duration_ms: 0,
+            error_count: 0,
+        };
+
+        let mut json_dump = Vec::new();
+        write_json(&mut json_dump, &metadata, &tree).unwrap();
+        let json_value: Value = serde_json::from_slice(&json_dump).unwrap();
+        let y = &json_value["tree"]["children"][1]["children"][0];
+        assert_eq!(
+            (&y["apparent_size"], &y["ino"], &y["nlink"]),
+            (&json!(100), &json!(7), &json!(2))
+        );
+        let (_, from_json) = load_json(json_value).unwrap();
+
+        let mut ncdu_dump = Vec::new();
+        write_ncdu(&mut ncdu_dump, &metadata, &tree).unwrap();
+        let ncdu_value: Value = serde_json::from_slice(&ncdu_dump).unwrap();
+        let y = &ncdu_value[3][2][1];
+        assert_eq!(
+            (&y["asize"], &y["hlnkc"], &y["dev"]),
+            (&json!(100), &json!(true), &json!(1))
+        );
+        let (_, from_ncdu) = load_ncdu(ncdu_value).unwrap();
+
+        for loaded in [from_json, from_ncdu] {
+            assert_eq!(loaded.usage.apparent, 100);
+            let y = &loaded.children[1].children[0];
+            assert_eq!(y.usage, Usage::default());
+            assert_eq!(y.stat.map(|stat| stat.size.apparent), Some(100));
+        }
+    }
+
+    #[test]
+    fn hard_links_inherit_the_parent_device() {
+        let link = |name: &str| json!({"name": name, "asize": 100, "ino": 7, "hlnkc": true});
+        let dump = json!([1, 2, {}, [
+            {"name": "/root", "dev": 1},
+            [{"name": "a"}, link("x")],
+            [{"name": "b"}, link("y")],
+            [{"name": "other", "dev": 2}, link("z")],
+        ]]);
+        let (_, tree) = load_ncdu(dump).unwrap();
+        // The second link on device 1 is counted once, the one on device 2 is a different file
+        assert_eq!(tree.usage.apparent, 200);
+    }
+
+    #[test]
+    fn skips_excluded_entries() {
+        let dump = json!([1, 2, {}, [
+            {"name": "/root"},
+            {"name": "kept", "asize": 1},
+            {"name": "ignored", "asize": 2, "excluded": "pattern"},
+            [{"name": "mnt", "excluded": "otherfs"}, {"name": "f", "asize": 4}],
+        ]]);
+        let (_, tree) = load_ncdu(dump).unwrap();
+        assert_eq!(tree.usage.apparent, 1);
+        assert_eq!(tree.children.len(), 1);
+    }
+}
@@ -1,15 +1,30 @@
+use super::diff::{format_change, DiffNode, DiffStatus};
+use super::dupes::DuplicateSet;
+use super::file_util::{EntryTimes, SizeMode, SortFields};
 use super::scan_job::{color_portion, PortionColor};
+use super::scan_tree::NodeKind;
+use super::top_files::TopFile;
 use bytesize::ByteSize;
-use colored::Colorize;
+use colored::{ColoredString, Colorize};
 use prettytable::format::Alignment;
 use prettytable::*;
-use std::cmp::Ordering;
 use std::time;
+use strum::IntoEnumIterator;
 
 #[derive(Debug, PartialEq)]
 pub enum ItemType {
     Directory,
     File,
+    Group,
+    // Items folded together, too small or too many to show on their own
+    Other,
+}
+
+// Change of an item since the previous pass of --watch
+#[derive(Debug, Clone, Copy)]
+pub struct Growth {
+    pub change: i64,
+    pub bytes_per_sec: f64,
 }
 
 #[derive(Debug)]
@@ -19,9 +34,53 @@ pub struct LineItem {
     pub start_time: time::Instant,
     pub completed_time: Option<time::Instant>,
     pub size_snapshot: u64,
+    pub secondary_size_snapshot: Option<u64>,
+    pub growth: Option<Growth>,
+    pub files: u64,
+    pub times: EntryTimes,
+    pub rank: Option<usize>,
 }
 
 impl LineItem {
+    /// Sums up `items` into a single "Other" item.
+    pub fn other<'a>(items: impl IntoIterator<Item = &'a LineItem>) -> Self {
+        let now = time::Instant::now();
+        let mut other = LineItem {
+            path: String::from("Other"),
+            item_type: ItemType::Other,
+            start_time: now,
+            completed_time: Some(now),
+            size_snapshot: 0,
+            secondary_size_snapshot: None,
+            growth: None,
+            files: 0,
+            times: EntryTimes::default(),
+            rank: None,
+        };
+        for item in items {
+            other.size_snapshot += item.size_snapshot;
+            other.secondary_size_snapshot = item
+                .secondary_size_snapshot
+                .map(|size| size + other.secondary_size_snapshot.unwrap_or(0));
+            other.growth = item.growth.map(|growth| Growth {
+                change: growth.change + other.growth.map_or(0, |other| other.change),
+                bytes_per_sec: growth.bytes_per_sec
+                    + other.growth.map_or(0.0, |other| other.bytes_per_sec),
+            });
+            other.files += item.files;
+        }
+        other
+    }
+
+    pub fn sort_fields(&self) -> SortFields<'_> {
+        SortFields {
+            name: &self.path,
+            size: self.size_snapshot,
+            files: self.files,
+            times: self.times,
+        }
+    }
+
     pub fn render_progress_row(&self, parent_size: u64, is_final: bool) -> Row {
         let mut row = Row::empty();
 
@@ -38,6 +97,11 @@ impl LineItem {
         let size_str = ByteSize::b(self.size_snapshot).to_string();
         row.add_cell(Cell::new_align(&size_str, Alignment::RIGHT));
 
+        if let Some(secondary_size) = self.secondary_size_snapshot {
+            let size_str = ByteSize::b(secondary_size).to_string().dimmed().to_string();
+            row.add_cell(Cell::new_align(&size_str, Alignment::RIGHT));
+        }
+
         let percent_str = &match parent_size {
             0 => String::from("00.00%"),
             _ => format!(
@@ -47,14 +111,26 @@ impl LineItem {
         };
         row.add_cell(Cell::new_align(percent_str, Alignment::RIGHT));
 
+        if let Some(growth) = self.growth {
+            let change = format_change(growth.change);
+            let rate = format!("{}/s", format_change(growth.bytes_per_sec.round() as i64));
+            let (change, rate) = match growth.change {
+                0 => (change.dimmed(), rate.dimmed()),
+                _ => (change.bold(), rate.normal()),
+            };
+            row.add_cell(Cell::new_align(&change.to_string(), Alignment::RIGHT));
+            row.add_cell(Cell::new_align(&rate.to_string(), Alignment::RIGHT));
+        }
+
         let path_str = &match self.item_type {
             ItemType::Directory => format!("{}", self.path.bright_cyan()),
-            ItemType::File => format!("{}", self.path.bright_white()),
+            ItemType::File | ItemType::Group => format!("{}", self.path.bright_white()),
+            ItemType::Other => format!("{}", self.path.white()),
         }
         .to_string();
         row.add_cell(Cell::new(path_str));
 
-        return row;
+        row
     }
 
     pub fn render_legend_row(
@@ -62,63 +138,171 @@ impl LineItem {
         i: usize,
         portion: PortionColor,
         aggregated_other: bool,
-    ) -> (Row, bool) {
+    ) -> Row {
         let item_name = self.path.clone();
         let item_name = match self.item_type {
             ItemType::Directory => item_name.bright_cyan(),
-            ItemType::File => item_name.bright_white(),
+            ItemType::File | ItemType::Group => item_name.bright_white(),
+            ItemType::Other => item_name.white(),
         }
         .to_string();
 
-        let show_index = !(portion == PortionColor::PortionLast && aggregated_other)
-            && self.item_type == ItemType::Directory;
-        let index_str = match show_index {
-            true => format!("[{}]", i + 1),
-            false => String::from(""),
+        let is_other = portion == PortionColor::PortionLast && aggregated_other;
+        let index_str = match self.item_type {
+            _ if is_other => String::from(""),
+            ItemType::Directory => format!("[{}]", i + 1),
+            // Groups aren't navigable, so only show their color
+            ItemType::Group => String::from("██"),
+            ItemType::File | ItemType::Other => String::from(""),
         };
         let index_str = color_portion(index_str, portion);
 
-        let item_size = ByteSize::b(self.size_snapshot).to_string();
-
-        (
-            Row::new(vec![
-                Cell::new(&index_str),
-                Cell::new(&item_name),
-                Cell::new_align(&item_size, Alignment::RIGHT),
-            ]),
-            show_index,
-        )
+        let mut row = Row::new(vec![Cell::new(&index_str), Cell::new(&item_name)]);
+        add_size_cells(&mut row, self.size_snapshot, self.secondary_size_snapshot);
+        row
     }
 
-    pub fn render_legend_row_other(label: &str, size: u64) -> Row {
-        Row::new(vec![
+    pub fn render_legend_row_other(label: &str, size: u64, secondary_size: Option<u64>) -> Row {
+        let mut row = Row::new(vec![
             Cell::new(&"".bright_white().to_string()),
             Cell::new(label),
-            Cell::new_align(&ByteSize::b(size).to_string(), Alignment::RIGHT),
+        ]);
+        add_size_cells(&mut row, size, secondary_size);
+        row
+    }
+
+    pub fn render_legend_row_mount(path: &str, show_secondary: bool) -> Row {
+        let mut row = Row::new(vec![
+            Cell::new(&"mount".dimmed().to_string()),
+            Cell::new(&path.bright_cyan().dimmed().to_string()),
+        ]);
+        add_size_cells(&mut row, 0, show_secondary.then_some(0));
+        row
+    }
+
+    pub fn render_top_file_row(
+        file: &TopFile,
+        size_mode: SizeMode,
+        secondary_mode: Option<SizeMode>,
+    ) -> Row {
+        let mtime = file
+            .mtime
+            .and_then(|mtime| chrono::DateTime::from_timestamp(mtime, 0))
+            .map(|mtime| {
+                mtime
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+
+        let mut row = Row::empty();
+        add_size_cells(
+            &mut row,
+            file.usage.get(size_mode),
+            secondary_mode.map(|mode| file.usage.get(mode)),
+        );
+        row.add_cell(Cell::new(&mtime.dimmed().to_string()));
+        row.add_cell(Cell::new(&file.path.bright_white().to_string()));
+        row
+    }
+
+    pub fn render_duplicate_rows(set: &DuplicateSet) -> Vec<Row> {
+        let copies = format!("{} × {}", set.paths.len(), ByteSize::b(set.size));
+        set.paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let (wasted, copies) = match i {
+                    0 => (
+                        ByteSize::b(set.wasted()).to_string(),
+                        copies.dimmed().to_string(),
+                    ),
+                    _ => (String::new(), String::new()),
+                };
+                Row::new(vec![
+                    Cell::new_align(&wasted, Alignment::RIGHT),
+                    Cell::new_align(&copies, Alignment::RIGHT),
+                    Cell::new(&path.bright_white().to_string()),
+                ])
+            })
+            .collect()
+    }
+
+    pub fn render_diff_row(i: usize, node: &DiffNode, size_mode: SizeMode) -> Row {
+        // Same markers and colors as the legend, so the two read alike
+        let portion = PortionColor::iter()
+            .nth(i)
+            .unwrap_or(PortionColor::PortionLast);
+        let (index_str, name) = match node.kind {
+            NodeKind::Directory => (format!("[{}]", i + 1), node.name.bright_cyan()),
+            NodeKind::File | NodeKind::Symlink => (String::new(), node.name.bright_white()),
+        };
+        let sizes = format!(
+            "{} → {}",
+            render_optional_size(node.old.map(|usage| usage.get(size_mode))),
+            render_optional_size(node.new.map(|usage| usage.get(size_mode))),
+        );
+
+        Row::new(vec![
+            Cell::new(&color_portion(index_str, portion)),
+            Cell::new_align(&format_change(node.change(size_mode)), Alignment::RIGHT),
+            Cell::new(&render_diff_status(node.status(size_mode)).to_string()),
+            Cell::new_align(&sizes.dimmed().to_string(), Alignment::RIGHT),
+            Cell::new(&name.to_string()),
         ])
     }
-}
 
-impl Ord for LineItem {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.size_snapshot.cmp(&other.size_snapshot)
+    pub fn render_child_breakdown_row(path: &str, size: u64, bar: &str) -> Row {
+        let mut row = Row::new(vec![Cell::new(&path.bright_cyan().to_string())]);
+        add_size_cells(&mut row, size, None);
+        row.add_cell(Cell::new(bar));
+        row
+    }
+
+    pub fn render_legend_header(primary: SizeMode, secondary: SizeMode) -> Row {
+        Row::new(vec![
+            Cell::new(""),
+            Cell::new(""),
+            Cell::new_align(
+                &size_mode_label(primary).dimmed().to_string(),
+                Alignment::RIGHT,
+            ),
+            Cell::new_align(
+                &size_mode_label(secondary).dimmed().to_string(),
+                Alignment::RIGHT,
+            ),
+        ])
     }
 }
 
-impl PartialOrd for LineItem {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let size_order = self.size_snapshot.cmp(&other.size_snapshot);
-        if size_order == Ordering::Equal {
-            return Some(self.path.cmp(&other.path));
-        }
-        Some(size_order)
+fn size_mode_label(mode: SizeMode) -> &'static str {
+    match mode {
+        SizeMode::Apparent => "apparent",
+        SizeMode::Disk => "on disk",
     }
 }
 
-impl PartialEq for LineItem {
-    fn eq(&self, other: &Self) -> bool {
-        self.size_snapshot == other.size_snapshot
+pub fn render_optional_size(size: Option<u64>) -> String {
+    size.map_or(String::from("-"), |size| ByteSize::b(size).to_string())
+}
+
+pub fn render_diff_status(status: DiffStatus) -> ColoredString {
+    match status {
+        DiffStatus::Added | DiffStatus::Removed => status.label().bold(),
+        _ => status.label().dimmed(),
     }
 }
 
-impl Eq for LineItem {}
+fn add_size_cells(row: &mut Row, size: u64, secondary_size: Option<u64>) {
+    row.add_cell(Cell::new_align(
+        &ByteSize::b(size).to_string(),
+        Alignment::RIGHT,
+    ));
+    if let Some(secondary_size) = secondary_size {
+        row.add_cell(Cell::new_align(
+            &ByteSize::b(secondary_size).to_string().dimmed().to_string(),
+            Alignment::RIGHT,
+        ));
+    }
+}
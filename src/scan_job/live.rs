@@ -0,0 +1,424 @@
+use super::file_util::{
+    get_dir_size, get_subtree_size, ErrorHandler, LinkClaims, ScanOptions, ScanStats, WalkStart,
+};
+use super::ignore_rules::{IgnoreMode, IgnoreRules};
+use super::scan_cache::ScanCache;
+use super::scan_job_args::ScanJobArgs;
+use super::scan_tree::{NodeKind, ScanNode};
+use crossbeam::channel::{unbounded, Receiver};
+use notify::event::{EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+// How long to keep collecting events before applying them together
+const BATCH_DELAY: Duration = Duration::from_millis(100);
+
+/// Why live updates had to stop.
+pub enum LiveError {
+    /// The watcher couldn't keep up with the tree, e.g. because it ran out of
+    /// inotify watches.
+    Unwatchable(notify::Error),
+    /// The event stream closed.
+    Disconnected,
+    /// There was no tree to watch, since the initial scan didn't produce one.
+    ScanFailed,
+}
+
+impl fmt::Display for LiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LiveError::Unwatchable(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+                write!(
+                    f,
+                    "ran out of file watches (see fs.inotify.max_user_watches)"
+                )
+            }
+            LiveError::Unwatchable(e) => write!(f, "{}", e),
+            LiveError::Disconnected => write!(f, "stopped receiving changes"),
+            LiveError::ScanFailed => write!(f, "the initial scan failed"),
+        }
+    }
+}
+
+/// A scan tree kept in sync with the file system by watching it for changes.
+pub struct LiveTree {
+    args: ScanJobArgs,
+    options: ScanOptions,
+    root: PathBuf,
+    root_dev: Option<u64>,
+    tree: ScanNode,
+    // Shared with the initial scan, so that refreshed links count once
+    links: Arc<LinkClaims>,
+    on_error: Arc<ErrorHandler>,
+    // Kept alive for as long as events should arrive
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl LiveTree {
+    pub fn watch(
+        args: ScanJobArgs,
+        options: ScanOptions,
+        tree: ScanNode,
+        links: Arc<LinkClaims>,
+        on_error: Arc<ErrorHandler>,
+    ) -> Result<Self, LiveError> {
+        let root = Path::new(&args.directory)
+            .canonicalize()
+            .map_err(|e| LiveError::Unwatchable(e.into()))?;
+        let root_dev = fs::metadata(&root).ok().map(|m| m.dev());
+        let (s, events) = unbounded();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = s.send(event);
+        })
+        .map_err(LiveError::Unwatchable)?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(LiveError::Unwatchable)?;
+
+        Ok(Self {
+            args,
+            options,
+            root,
+            root_dev,
+            tree,
+            links,
+            on_error,
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    pub fn tree(&self) -> &ScanNode {
+        &self.tree
+    }
+
+    /// Waits for the next changes and applies them to the tree.
+    pub fn update(&mut self) -> Result<(), LiveError> {
+        let first = self.events.recv().map_err(|_| LiveError::Disconnected)?;
+        std::thread::sleep(BATCH_DELAY);
+        let events = std::iter::once(first).chain(self.events.try_iter());
+
+        // Paths that changed, and whether they were created, removed or renamed
+        let mut changed = HashMap::new();
+        let mut rescan = false;
+        for event in events {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+                    return Err(LiveError::Unwatchable(e))
+                }
+                Err(e) => {
+                    (self.on_error)(format!("Error watching '{}': {}", self.root.display(), e));
+                    continue;
+                }
+            };
+
+            // Events were dropped, so nothing short of a rescan can be trusted
+            rescan |= event.need_rescan();
+            let replaced = matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Remove(_)
+                    | EventKind::Modify(ModifyKind::Name(_))
+            );
+            match event.kind {
+                EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => {}
+                _ => {
+                    for path in event.paths {
+                        *changed.entry(path).or_default() |= replaced;
+                    }
+                }
+            }
+        }
+
+        match rescan {
+            true => self.rescan(),
+            false => changed
+                .iter()
+                .for_each(|(path, &replaced)| self.refresh(path, replaced)),
+        }
+        Ok(())
+    }
+
+    fn rescan(&mut self) {
+        let scan_root = Path::new(&self.args.directory);
+        self.links.release(scan_root);
+        let tree = get_dir_size(
+            &self.args.directory,
+            self.options.clone(),
+            self.stats(),
+            Arc::new(ScanCache::in_memory()),
+            None,
+            self.on_error.clone(),
+        );
+        if let Some(tree) = tree {
+            self.tree = tree;
+        }
+    }
+
+    // Re-reads `path` from disk and replaces whatever the tree had for it,
+    // counting it the way the walker would have. A directory that's still
+    // there is only walked again if it was `replaced` by another one
+    fn refresh(&mut self, path: &Path, replaced: bool) {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return;
+        };
+        let (Some(parent), Some(name)) = (relative.parent(), relative.file_name()) else {
+            return;
+        };
+        let metadata = fs::symlink_metadata(path).ok();
+        // Changes to a directory's own metadata don't change what's inside it
+        let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+        if is_dir && !replaced && self.is_scanned_dir(relative) {
+            return;
+        }
+
+        // Named the way the walker names it, so that it claims the same links
+        let scan_path = Path::new(&self.args.directory).join(relative);
+        self.tree.remove(relative);
+        self.links.release(&scan_path);
+        let Some(metadata) = metadata else {
+            return;
+        };
+        let Some(start) = self.walk_start(&scan_path, parent, name, metadata.is_dir()) else {
+            return;
+        };
+        let node = match metadata.is_dir() {
+            true if self.crosses_mount(&metadata) => return,
+            true => {
+                let scan_path = scan_path.to_string_lossy();
+                let node = get_subtree_size(
+                    &scan_path,
+                    self.options.clone(),
+                    self.stats(),
+                    start,
+                    self.on_error.clone(),
+                );
+                match node {
+                    Some(node) => node,
+                    None => return,
+                }
+            }
+            false => ScanNode::file(
+                String::new(),
+                match metadata.is_symlink() {
+                    true => NodeKind::Symlink,
+                    false => NodeKind::File,
+                },
+                self.options
+                    .hard_links
+                    .share(&metadata, self.links.is_first(&metadata, &scan_path, true)),
+                Some(&metadata),
+            ),
+        };
+        self.tree.insert(
+            parent,
+            ScanNode {
+                name: name.to_string_lossy().to_string(),
+                ..node
+            },
+        );
+    }
+
+    fn is_scanned_dir(&self, relative: &Path) -> bool {
+        self.tree
+            .find(&Path::new(&self.tree.name).join(relative))
+            .is_some()
+    }
+
+    fn crosses_mount(&self, metadata: &fs::Metadata) -> bool {
+        self.options.one_file_system && self.root_dev.is_some_and(|dev| dev != metadata.dev())
+    }
+
+    fn stats(&self) -> Arc<ScanStats> {
+        Arc::new(ScanStats {
+            hard_links: self.links.clone(),
+            ..Default::default()
+        })
+    }
+
+    // What the walker would have known on reaching the entry at `scan_path`,
+    // or None if it wouldn't have counted it
+    fn walk_start(
+        &self,
+        scan_path: &Path,
+        parent: &Path,
+        name: &OsStr,
+        is_dir: bool,
+    ) -> Option<WalkStart> {
+        let filter = &self.options.filter;
+        let ancestors: Vec<_> = scan_path
+            .ancestors()
+            .take_while(|ancestor| *ancestor != Path::new(&self.args.directory))
+            .collect();
+        if ancestors
+            .iter()
+            .any(|ancestor| filter.is_excluded(ancestor))
+        {
+            return None;
+        }
+        // Directories are walked either way, to find included files inside
+        let included = !filter.has_includes()
+            || ancestors
+                .iter()
+                .any(|ancestor| filter.is_included(ancestor));
+        if !included && !is_dir {
+            return None;
+        }
+
+        let (ignored, ignore_rules) = self.ignore_rules(parent);
+        let ignored = ignored
+            || ignore_rules
+                .as_ref()
+                .is_some_and(|r| r.is_ignored(name, is_dir));
+        match self.options.ignore_mode {
+            IgnoreMode::Respect if ignored => return None,
+            IgnoreMode::OnlyIgnored if !ignored && !is_dir => return None,
+            _ => {}
+        }
+
+        Some(WalkStart {
+            root_dev: self.root_dev,
+            included,
+            ignored,
+            ignore_rules: ignore_rules.filter(|_| !ignored),
+        })
+    }
+
+    // Rules in effect for the entries of `parent`, or whether `parent` is
+    // ignored itself
+    fn ignore_rules(&self, parent: &Path) -> (bool, Option<Arc<IgnoreRules>>) {
+        if self.options.ignore_mode == IgnoreMode::Off {
+            return (false, None);
+        }
+
+        let on_error = |e| (self.on_error)(e);
+        let inherited = IgnoreRules::for_ancestors(&self.root, &on_error);
+        let mut rules = IgnoreRules::for_dir(self.root.clone(), inherited, &on_error);
+        for component in parent.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            if rules.is_ignored(name, true) {
+                return (true, None);
+            }
+            rules = IgnoreRules::for_dir(rules.child_dir(name), Some(rules), &on_error);
+        }
+        (false, Some(rules))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn live(root: &Path, flags: &[&str]) -> LiveTree {
+        let mut argv = vec!["df2", root.to_str().unwrap()];
+        argv.extend(flags);
+        let args = ScanJobArgs::parse_from(argv);
+        let options = ScanOptions {
+            record_tree: true,
+            use_cache: false,
+            track_links: true,
+            ..args.scan_options().unwrap()
+        };
+        let stats = Arc::new(ScanStats::default());
+        let on_error: Arc<ErrorHandler> = Arc::new(|e| panic!("{}", e));
+        let tree = get_dir_size(
+            &args.directory,
+            options.clone(),
+            stats.clone(),
+            Arc::new(ScanCache::in_memory()),
+            None,
+            on_error.clone(),
+        )
+        .unwrap();
+        LiveTree::watch(args, options, tree, stats.hard_links.clone(), on_error)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn size(live: &LiveTree) -> u64 {
+        live.tree().usage.apparent
+    }
+
+    #[test]
+    fn refreshed_hard_links_count_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/file"), [0; 1000]).unwrap();
+        fs::hard_link(root.join("a/file"), root.join("a/link")).unwrap();
+        fs::write(root.join("a/single"), [0; 10]).unwrap();
+        let mut live = live(&root, &[]);
+        assert_eq!(size(&live), 1010);
+
+        // Links in a new directory, and the link that was counted first
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::hard_link(root.join("a/file"), root.join("b/link")).unwrap();
+        live.refresh(&root.join("b"), true);
+        live.refresh(&root.join("a/file"), false);
+        live.refresh(&root.join("a/link"), false);
+        assert_eq!(size(&live), 1010);
+
+        // A file that only now has a second link, which the watcher
+        // doesn't report for the first one
+        live.refresh(&root.join("a/single"), false);
+        fs::hard_link(root.join("a/single"), root.join("b/single")).unwrap();
+        live.refresh(&root.join("b/single"), true);
+        assert_eq!(size(&live), 1010);
+    }
+
+    #[test]
+    fn refreshes_skip_what_the_scan_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\ntarget/\n").unwrap();
+        let mut live = live(&root, &["--respect-ignore", "-e", "**/skip"]);
+        let initial = size(&live);
+
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("target/out"), [0; 100]).unwrap();
+        fs::write(root.join("debug.log"), [0; 100]).unwrap();
+        fs::create_dir_all(root.join("src/skip")).unwrap();
+        fs::write(root.join("src/skip/file"), [0; 100]).unwrap();
+        fs::write(root.join("src/main.rs"), [0; 10]).unwrap();
+        for path in ["target", "debug.log", "src"] {
+            live.refresh(&root.join(path), true);
+        }
+        assert_eq!(size(&live), initial + 10);
+    }
+
+    #[test]
+    fn refreshes_directories_replaced_within_a_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/old"), [0; 100]).unwrap();
+        let mut live = live(&root, &[]);
+        assert_eq!(size(&live), 100);
+
+        // The new directory can fill up before it's watched, so only the
+        // directory itself is reported
+        fs::remove_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/new"), [0; 10]).unwrap();
+        live.refresh(&root.join("a"), true);
+        live.refresh(&root.join("a/old"), true);
+        assert_eq!(size(&live), 10);
+
+        // Otherwise its entries are refreshed on their own
+        fs::write(root.join("a/unseen"), [0; 1]).unwrap();
+        live.refresh(&root.join("a"), false);
+        assert_eq!(size(&live), 10);
+    }
+}
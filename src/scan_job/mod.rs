@@ -1,28 +1,65 @@
-pub mod file_util;
+pub(crate) mod breakdown;
+pub(crate) mod diff;
+pub(crate) mod dupes;
+mod export;
+pub(crate) mod file_util;
+mod ignore_rules;
+pub(crate) mod import;
 mod line_item;
 mod lines_component;
+mod live;
+pub(crate) mod navigator;
+mod owners;
+mod path_filter;
+pub(crate) mod scan_cache;
+#[allow(clippy::module_inception)]
 mod scan_job;
-pub mod scan_job_args;
+pub(crate) mod scan_job_args;
+pub(crate) mod scan_tree;
+pub(crate) mod scanner;
+pub(crate) mod top_files;
 
-use crossterm;
+use bytesize::ByteSize;
+use colored::Colorize;
+use diff::{format_change, DiffNode};
+pub use export::ScanMetadata;
+use file_util::ScanOptions;
+pub use ignore_rules::IgnoreMode;
+use line_item::LineItem;
 use lines_component::LinesComponent;
-use scan_job::ScanJob;
-use scan_job_args::ScanJobArgs;
-use std::collections::HashMap;
+use live::{LiveError, LiveTree};
+use navigator::DiffNavigator;
+use prettytable::Table;
+use scan_cache::ScanCache;
+use scan_job::{PreviousPass, ScanJob, TABLE_FROMAT};
+use scan_job_args::{DiffArgs, DupesArgs, OutputFormat, ScanJobArgs};
+use scan_tree::ScanNode;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use superconsole::components::Blank;
-use superconsole::{Component, Dimensions, DrawMode, SuperConsole};
+use superconsole::{Component, Dimensions, DrawMode, Lines, SuperConsole};
 
-pub fn scan_dir(args: ScanJobArgs, size_cache: Arc<Mutex<HashMap<String, u64>>>) -> Vec<String> {
-    let job = Arc::new(ScanJob::new(args.clone()));
+pub fn scan_dir(
+    args: ScanJobArgs,
+    options: ScanOptions,
+    cache: Arc<ScanCache>,
+) -> Option<ScanNode> {
+    if args.is_plain() {
+        return scan_dir_plain(args, options, cache);
+    }
+
+    let job = Arc::new(ScanJob::new(args.clone(), options));
     let console = Arc::new(Mutex::new(
         SuperConsole::new()
             .ok_or_else(|| anyhow::anyhow!("Not a TTY"))
             .unwrap(),
     ));
 
-    crossbeam::thread::scope(|s| {
+    let tree = crossbeam::thread::scope(|s| {
         let stop_flag = Arc::new(AtomicBool::new(false));
 
         let job_clone = job.clone();
@@ -30,33 +67,15 @@ pub fn scan_dir(args: ScanJobArgs, size_cache: Arc<Mutex<HashMap<String, u64>>>)
         let console_clone = console.clone();
         s.spawn(move |_| job_clone.render_until_flag(console_clone, stop_flag_clone));
 
-        let console_clone = console.clone();
-        job.execute(
-            size_cache,
-            Arc::new(move |msg: String| {
-                {
-                    if !args.verbose {
-                        return;
-                    }
-
-                    let lines = LinesComponent::from_str(&msg)
-                        .draw(
-                            Dimensions::new(
-                                crossterm::terminal::size().unwrap().0.into(),
-                                usize::MAX,
-                            ),
-                            DrawMode::Final,
-                        )
-                        .unwrap();
-                    console_clone.lock().unwrap().emit(lines)
-                }
-                .into()
-            }),
-        );
+        let tree = job.execute(cache, console_error_handler(console.clone(), args.verbose));
 
         stop_flag.store(true, Ordering::Relaxed);
+        tree
     })
     .unwrap();
+    if let Some(tree) = &tree {
+        job.load_breakdown(tree);
+    }
 
     if let Ok(console) = Arc::try_unwrap(console) {
         let mut console = console.into_inner().unwrap();
@@ -72,6 +91,333 @@ pub fn scan_dir(args: ScanJobArgs, size_cache: Arc<Mutex<HashMap<String, u64>>>)
         eprintln!("Failed to unlock console");
     }
 
-    let job = Arc::try_unwrap(job).unwrap();
-    job.get_result()
+    tree
+}
+
+/// Rescans `args.directory` every `interval` for as long as the process runs,
+/// keeping the live view open in between.
+pub fn watch_dir(args: ScanJobArgs, options: ScanOptions, interval: Duration) {
+    let console = Arc::new(Mutex::new(
+        SuperConsole::new()
+            .ok_or_else(|| anyhow::anyhow!("Not a TTY"))
+            .unwrap(),
+    ));
+    watch_passes(console, args, options, interval, None);
+}
+
+fn watch_passes(
+    console: Arc<Mutex<SuperConsole>>,
+    args: ScanJobArgs,
+    options: ScanOptions,
+    interval: Duration,
+    mut previous_pass: Option<PreviousPass>,
+) {
+    // Cached entries would hide changes to files in place
+    let options = ScanOptions {
+        use_cache: false,
+        ..options
+    };
+    let cache = Arc::new(ScanCache::in_memory());
+
+    loop {
+        let started = Instant::now();
+        let job = Arc::new(
+            ScanJob::new(args.clone(), options.clone()).with_previous_pass(previous_pass.take()),
+        );
+
+        crossbeam::thread::scope(|s| {
+            let stop_flag = Arc::new(AtomicBool::new(false));
+
+            let job_clone = job.clone();
+            let stop_flag_clone = stop_flag.clone();
+            let console_clone = console.clone();
+            s.spawn(move |_| job_clone.render_until_flag(console_clone, stop_flag_clone));
+
+            let tree = job.execute(
+                cache.clone(),
+                console_error_handler(console.clone(), args.verbose),
+            );
+            if let Some(tree) = &tree {
+                job.load_breakdown(tree);
+            }
+
+            thread::sleep(interval.saturating_sub(started.elapsed()));
+            stop_flag.store(true, Ordering::Relaxed);
+        })
+        .unwrap();
+
+        previous_pass = Some(job.finish_pass());
+    }
+}
+
+/// Scans `args.directory` once, then keeps the view up to date by watching
+/// the tree for changes. Falls back to rescanning every `fallback` if the tree
+/// can't be watched, e.g. because it has more directories than inotify
+/// watches are available.
+pub fn live_dir(args: ScanJobArgs, options: ScanOptions, fallback: Duration) {
+    let options = ScanOptions {
+        record_tree: true,
+        use_cache: false,
+        track_links: true,
+        ..options
+    };
+    let console = Arc::new(Mutex::new(
+        SuperConsole::new()
+            .ok_or_else(|| anyhow::anyhow!("Not a TTY"))
+            .unwrap(),
+    ));
+    let job = Arc::new(ScanJob::new(args.clone(), options.clone()).live());
+    let on_error = console_error_handler(console.clone(), args.verbose);
+
+    let error = crossbeam::thread::scope(|s| {
+        let stop_flag = Arc::new(AtomicBool::new(false));
+
+        let job_clone = job.clone();
+        let stop_flag_clone = stop_flag.clone();
+        let console_clone = console.clone();
+        s.spawn(move |_| job_clone.render_until_flag(console_clone, stop_flag_clone));
+
+        let tree = job.execute(Arc::new(ScanCache::in_memory()), on_error.clone());
+        let error = match tree {
+            Some(tree) => {
+                job.load_view(&tree);
+                let links = job.link_claims();
+                match LiveTree::watch(args.clone(), options.clone(), tree, links, on_error) {
+                    Ok(mut live) => loop {
+                        if let Err(e) = live.update() {
+                            break e;
+                        }
+                        job.load_view(live.tree());
+                    },
+                    Err(e) => e,
+                }
+            }
+            None => LiveError::ScanFailed,
+        };
+
+        stop_flag.store(true, Ordering::Relaxed);
+        error
+    })
+    .unwrap();
+
+    console.lock().unwrap().emit(Lines::from_multiline_string(
+        &format!(
+            "Can't watch '{}' for changes: {}. Rescanning every {} instead",
+            args.directory,
+            error,
+            humantime::format_duration(fallback)
+        ),
+        Default::default(),
+    ));
+    let previous_pass = job.finish_pass();
+    watch_passes(console, args, options, fallback, Some(previous_pass));
+}
+
+// Prints scan errors above the live view when verbose
+fn console_error_handler(
+    console: Arc<Mutex<SuperConsole>>,
+    verbose: bool,
+) -> Arc<impl Fn(String) + Send + Sync + 'static> {
+    Arc::new(move |msg: String| {
+        if !verbose {
+            return;
+        }
+
+        let lines = LinesComponent::from_str(&msg)
+            .draw(
+                Dimensions::new(crossterm::terminal::size().unwrap().0.into(), usize::MAX),
+                DrawMode::Final,
+            )
+            .unwrap();
+        console.lock().unwrap().emit(lines)
+    })
+}
+
+// Prints scan errors to stderr when verbose, for output without a live view
+fn stderr_error_handler(verbose: bool) -> impl Fn(String) + Send + Sync + 'static {
+    move |msg: String| {
+        if verbose {
+            eprintln!("{}", msg);
+        }
+    }
+}
+
+fn scan_dir_plain(
+    mut args: ScanJobArgs,
+    options: ScanOptions,
+    cache: Arc<ScanCache>,
+) -> Option<ScanNode> {
+    let dimensions = plain_dimensions(&mut args);
+    let job = ScanJob::new(args.clone(), options);
+    let tree = job.execute(cache, Arc::new(stderr_error_handler(args.verbose)));
+    if let Some(tree) = &tree {
+        job.load_breakdown(tree);
+    }
+
+    print_plain(&job.draw(dimensions, DrawMode::Final).unwrap());
+    tree
+}
+
+// Without a terminal there's no width to fill and nothing to truncate to
+fn plain_dimensions(args: &mut ScanJobArgs) -> Dimensions {
+    match crossterm::terminal::size() {
+        Ok((width, _)) if io::stdout().is_terminal() => Dimensions::new(width.into(), usize::MAX),
+        _ => {
+            args.full_width = false;
+            Dimensions::new(usize::MAX, usize::MAX)
+        }
+    }
+}
+
+fn print_plain(lines: &Lines) {
+    let use_color = colored::control::SHOULD_COLORIZE.should_colorize();
+    let mut stdout = io::stdout().lock();
+    for line in lines.iter() {
+        let _ = match use_color {
+            true => writeln!(stdout, "{}", line.render()),
+            false => writeln!(stdout, "{}", line.to_unstyled()),
+        };
+    }
+}
+
+pub fn export_dir(args: ScanJobArgs) -> anyhow::Result<()> {
+    let result = args
+        .scanner()
+        .on_error(stderr_error_handler(args.verbose))
+        .scan()?;
+
+    write_export(args.output, &result.metadata, &result.tree)
+}
+
+pub fn show_duplicates(args: &DupesArgs, verbose: bool) -> anyhow::Result<()> {
+    let sets = args
+        .scan
+        .scanner(&args.directory)
+        .on_error(stderr_error_handler(verbose))
+        .find_duplicates()?;
+
+    if sets.is_empty() {
+        print_plain(&Lines::from_multiline_string(
+            "No duplicate files found",
+            Default::default(),
+        ));
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*TABLE_FROMAT);
+    for set in sets.iter().take(args.limit.unwrap_or(usize::MAX)) {
+        for row in LineItem::render_duplicate_rows(set) {
+            table.add_row(row);
+        }
+    }
+    let reclaimable: u64 = sets.iter().map(|set| set.wasted()).sum();
+    let summary = format!(
+        "{} reclaimable in {} duplicate {}",
+        ByteSize::b(reclaimable),
+        sets.len(),
+        match sets.len() {
+            1 => "set",
+            _ => "sets",
+        }
+    );
+
+    print_plain(&Lines::from_colored_multiline_string(&format!(
+        "{}\n{}",
+        table,
+        summary.bold()
+    )));
+    Ok(())
+}
+
+pub fn show_diff(args: &DiffArgs, verbose: bool) -> anyhow::Result<()> {
+    let load = |path: &Path| {
+        import::load_dump(path)
+            .map(|(_, tree)| tree)
+            .map_err(|e| anyhow::anyhow!("Failed to load '{}': {}", path.display(), e))
+    };
+    let old = load(&args.old)?;
+    // The later side is either another export or a directory to scan now
+    let new = match args.new.is_dir() {
+        true => {
+            args.scan
+                .scanner(&args.new.to_string_lossy())
+                .size_mode(args.size_mode())
+                .on_error(stderr_error_handler(verbose))
+                .scan()?
+                .tree
+        }
+        false => load(&args.new)?,
+    };
+
+    let size_mode = args.size_mode();
+    let diff = DiffNode::new(&old, &new, size_mode);
+    if args.interactive_mode && io::stdout().is_terminal() {
+        return DiffNavigator::new(diff, size_mode).run();
+    }
+
+    if diff.children.is_empty() {
+        print_plain(&Lines::from_multiline_string(
+            "No changes",
+            Default::default(),
+        ));
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*TABLE_FROMAT);
+    for (i, child) in diff.children.iter().enumerate() {
+        table.add_row(LineItem::render_diff_row(i, child, size_mode));
+    }
+    let summary = format!(
+        "{} {} ({} → {})",
+        diff.name,
+        format_change(diff.change(size_mode)),
+        ByteSize::b(diff.old_size(size_mode)),
+        ByteSize::b(diff.new_size(size_mode))
+    );
+
+    print_plain(&Lines::from_colored_multiline_string(&format!(
+        "{}\n{}",
+        table,
+        summary.bold()
+    )));
+    Ok(())
+}
+
+pub fn write_export(
+    format: OutputFormat,
+    metadata: &ScanMetadata,
+    tree: &ScanNode,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    match format {
+        OutputFormat::Json => export::write_json(&mut writer, metadata, tree)?,
+        OutputFormat::Ncdu => export::write_ncdu(&mut writer, metadata, tree)?,
+        OutputFormat::Console => unreachable!("console output is not an export format"),
+    }
+    writeln!(writer)?;
+    Ok(())
+}
+
+pub fn show_tree(mut args: ScanJobArgs, node: &ScanNode) {
+    let dimensions = args.is_plain().then(|| plain_dimensions(&mut args));
+    let job = ScanJob::new(args, ScanOptions::default());
+    job.load_tree(node);
+    if let Some(dimensions) = dimensions {
+        print_plain(&job.draw(dimensions, DrawMode::Final).unwrap());
+        return;
+    }
+
+    let mut console = SuperConsole::new()
+        .ok_or_else(|| anyhow::anyhow!("Not a TTY"))
+        .unwrap();
+    console.emit(
+        job.draw(
+            Dimensions::new(crossterm::terminal::size().unwrap().0.into(), usize::MAX),
+            DrawMode::Final,
+        )
+        .unwrap(),
+    );
+    console.finalize(&Blank).unwrap();
 }
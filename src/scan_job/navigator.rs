@@ -0,0 +1,728 @@
+use super::breakdown::{Breakdown, OwnerField, TimeField};
+use super::diff::{format_change, DiffNode};
+use super::file_util::{ScanOptions, SizeMode, Usage};
+use super::line_item::{render_diff_status, render_optional_size};
+use super::scan_job::ScanJob;
+use super::scan_job_args::ScanJobArgs;
+use super::scan_tree::{NodeKind, ScanNode};
+use bytesize::ByteSize;
+use colored::Colorize;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use superconsole::{Component, Dimensions, DrawMode, Lines};
+
+const MINI_BAR_WIDTH: usize = 10;
+
+pub enum NavigatorExit {
+    Quit,
+    Parent,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum DeleteMode {
+    Trash,
+    Permanent,
+}
+
+struct PendingDelete {
+    mode: DeleteMode,
+    path: PathBuf,
+    usage: Usage,
+}
+
+// Restores the terminal even if drawing fails part way through
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> anyhow::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub struct Navigator {
+    args: ScanJobArgs,
+    root: ScanNode,
+    // Child indices leading from the root to the current directory
+    path: Vec<usize>,
+    // Selected child of each directory along the path
+    selected: Vec<usize>,
+    scroll: usize,
+    can_leave_root: bool,
+    allow_delete: bool,
+    pending_delete: Option<PendingDelete>,
+    // Grouping shown in the stacked bar instead of the selected directory's entries
+    breakdown: Option<Breakdown>,
+    message: Option<String>,
+}
+
+impl Navigator {
+    pub fn new(
+        args: ScanJobArgs,
+        mut root: ScanNode,
+        can_leave_root: bool,
+        allow_delete: bool,
+    ) -> Self {
+        root.sort_by(args.sort, args.sort_order(), args.size_mode());
+        Self {
+            breakdown: args.breakdown(),
+            args,
+            root,
+            path: Vec::new(),
+            selected: vec![0],
+            scroll: 0,
+            can_leave_root,
+            allow_delete,
+            pending_delete: None,
+            message: None,
+        }
+    }
+
+    pub fn run(mut self) -> anyhow::Result<NavigatorExit> {
+        let _guard = TerminalGuard::enter()?;
+        let mut stdout = io::stdout();
+
+        loop {
+            self.draw(&mut stdout)?;
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    self.message = None;
+                    if let Some(exit) = self.handle_key(key) {
+                        return Ok(exit);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<NavigatorExit> {
+        if let Some(pending) = self.pending_delete.take() {
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => self.delete(pending),
+                _ => self.message = Some(String::from("Cancelled")),
+            }
+            return None;
+        }
+
+        let len = self.current().children.len();
+        if is_quit(key) {
+            return Some(NavigatorExit::Quit);
+        }
+        if move_selection(self.selected.last_mut().unwrap(), len, key.code) {
+            return None;
+        }
+
+        match key.code {
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.descend(),
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => return self.ascend(),
+            KeyCode::Char('d') | KeyCode::Delete => self.request_delete(DeleteMode::Trash),
+            KeyCode::Char('D') => self.request_delete(DeleteMode::Permanent),
+            KeyCode::Char('t') => self.toggle_breakdown(Breakdown::Type),
+            KeyCode::Char('a') => {
+                self.toggle_breakdown(Breakdown::Age(self.args.by_age.unwrap_or(TimeField::Mtime)))
+            }
+            KeyCode::Char('o') => self.toggle_breakdown(Breakdown::Owner(
+                self.args.by_owner.unwrap_or(OwnerField::User),
+            )),
+            _ => {}
+        }
+        None
+    }
+
+    fn descend(&mut self) {
+        let selected = *self.selected.last().unwrap();
+        match self.current().children.get(selected) {
+            Some(child) if child.kind == NodeKind::Directory => {
+                self.path.push(selected);
+                self.selected.push(0);
+                self.scroll = 0;
+            }
+            Some(_) => self.message = Some(String::from("Not a directory")),
+            None => {}
+        }
+    }
+
+    fn ascend(&mut self) -> Option<NavigatorExit> {
+        if self.path.is_empty() {
+            if self.can_leave_root {
+                return Some(NavigatorExit::Parent);
+            }
+            self.message = Some(String::from("Already at the top of the loaded scan"));
+            return None;
+        }
+
+        self.path.pop();
+        self.selected.pop();
+        self.scroll = 0;
+        None
+    }
+
+    fn toggle_breakdown(&mut self, breakdown: Breakdown) {
+        self.breakdown = match self.breakdown {
+            Some(current) if current == breakdown => None,
+            _ => Some(breakdown),
+        };
+    }
+
+    fn request_delete(&mut self, mode: DeleteMode) {
+        if !self.allow_delete {
+            self.message = Some(String::from("Deleting is disabled for loaded scans"));
+            return;
+        }
+
+        let selected = *self.selected.last().unwrap();
+        if let Some(child) = self.current().children.get(selected) {
+            self.pending_delete = Some(PendingDelete {
+                mode,
+                path: self.current_path().join(&child.name),
+                usage: child.usage,
+            });
+        }
+    }
+
+    // Removes the selected item from disk and from the tree
+    fn delete(&mut self, pending: PendingDelete) {
+        let result = match pending.mode {
+            DeleteMode::Trash => trash::delete(&pending.path).map_err(anyhow::Error::from),
+            DeleteMode::Permanent => remove_path(&pending.path),
+        };
+        if let Err(e) = result {
+            self.message = Some(format!(
+                "Failed to delete '{}': {}",
+                pending.path.display(),
+                e
+            ));
+            return;
+        }
+
+        self.remove_selected();
+        self.message = Some(match pending.mode {
+            DeleteMode::Trash => format!("Moved '{}' to the trash", pending.path.display()),
+            DeleteMode::Permanent => format!("Deleted '{}'", pending.path.display()),
+        });
+    }
+
+    // Takes the selected item out of the tree, and its usage and times out of
+    // every directory above it
+    fn remove_selected(&mut self) {
+        let selected = *self.selected.last().unwrap();
+        let Some(name) = self
+            .current()
+            .children
+            .get(selected)
+            .map(|c| c.name.clone())
+        else {
+            return;
+        };
+        let current_path = self.current_path();
+        let relative = current_path
+            .strip_prefix(&self.root.name)
+            .unwrap_or(&current_path);
+        self.root.remove(&relative.join(name));
+
+        let len = self.current().children.len();
+        let selected = self.selected.last_mut().unwrap();
+        *selected = (*selected).min(len.saturating_sub(1));
+    }
+
+    fn current(&self) -> &ScanNode {
+        node_at(&self.root, &self.path)
+    }
+
+    fn current_path(&self) -> PathBuf {
+        path_at(&self.root, &self.path)
+    }
+
+    // Stacked bar of the selected directory, or of the current one when a file is selected
+    fn render_bar(&self, width: usize) -> anyhow::Result<Lines> {
+        let current = self.current();
+        let selected = current.children.get(*self.selected.last().unwrap());
+        let (node, path) = match selected {
+            Some(child) if child.kind == NodeKind::Directory => {
+                (child, self.current_path().join(&child.name))
+            }
+            _ => (current, self.current_path()),
+        };
+
+        let mut args = self.args.clone();
+        args.directory = path.to_string_lossy().to_string();
+        args.full_width = true;
+        args.list_items = false;
+        args.top_files = None;
+        let job = ScanJob::new(args, ScanOptions::default()).with_breakdown(self.breakdown);
+        job.load_tree(node);
+        job.draw(Dimensions::new(width, usize::MAX), DrawMode::Final)
+    }
+
+    fn render_row(&self, child: &ScanNode, total: u64, is_selected: bool) -> String {
+        let size = child.usage.get(self.args.size_mode());
+        let proportion = match total {
+            0 => 0.0,
+            _ => size as f64 / total as f64,
+        };
+        let filled = ((proportion * MINI_BAR_WIDTH as f64).round() as usize).min(MINI_BAR_WIDTH);
+        let mini_bar = format!(
+            "{}{}",
+            "█".repeat(filled),
+            " ".repeat(MINI_BAR_WIDTH - filled)
+        );
+
+        let name = match child.kind {
+            NodeKind::Directory => format!("{}/", child.name).bright_cyan(),
+            NodeKind::File | NodeKind::Symlink => child.name.bright_white(),
+        };
+        let row = format!(
+            " {:>10} {:>7.2}% [{}] {}",
+            ByteSize::b(size).to_string(),
+            proportion * 100.0,
+            mini_bar,
+            name
+        );
+        match is_selected {
+            true => row.reversed().to_string(),
+            false => row,
+        }
+    }
+
+    fn draw(&mut self, stdout: &mut io::Stdout) -> anyhow::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let current = self.current();
+        let total = current.usage.get(self.args.size_mode());
+
+        let mut bar = self.render_bar(width)?;
+        // Keep at least a few list rows on small terminals
+        bar.truncate_lines_bottom(height.saturating_sub(6));
+        let list_height = height.saturating_sub(bar.len() + 4);
+        let selected = *self.selected.last().unwrap();
+        scroll_to(&mut self.scroll, selected, list_height);
+
+        let current = self.current();
+        let path = self.current_path();
+        let mut rows = list_rows(
+            format!(" df2 {}", path.display()),
+            &current.children,
+            self.scroll,
+            selected,
+            list_height,
+            " (empty)",
+            |child, is_selected| self.render_row(child, total, is_selected),
+        );
+        rows.extend(bar.iter().map(|line| line.render()));
+        rows.push(String::new());
+
+        let mut keys = vec![
+            "↑↓/jk move",
+            "⏎ open",
+            "⌫ up",
+            "t types",
+            "a ages",
+            "o owners",
+        ];
+        if self.allow_delete {
+            keys.extend(["d trash", "D delete"]);
+        }
+        keys.push("q quit");
+        let status = match &self.message {
+            Some(message) => message.bright_yellow().to_string(),
+            None => format!(
+                " {}  Total: {}  Items: {}  {}",
+                path.display(),
+                ByteSize::b(total),
+                current.usage.files,
+                keys.join("  ").dimmed()
+            ),
+        };
+        rows.push(status);
+        if let Some(pending) = &self.pending_delete {
+            self.overlay_dialog(&mut rows, pending, width, list_height);
+        }
+
+        write_rows(stdout, &rows, width, height)
+    }
+
+    // Draws the delete confirmation as a box over the middle of the list
+    fn overlay_dialog(
+        &self,
+        rows: &mut [String],
+        pending: &PendingDelete,
+        width: usize,
+        list_height: usize,
+    ) {
+        let size = pending.usage.get(self.args.size_mode());
+        let (title, warning) = match pending.mode {
+            DeleteMode::Trash => ("Move to trash?", None),
+            DeleteMode::Permanent => ("Delete permanently?", Some("This can't be undone")),
+        };
+        let mut body = vec![
+            pending.path.display().to_string(),
+            format!(
+                "{} ({} bytes) in {} {}",
+                ByteSize::b(size),
+                size,
+                pending.usage.files,
+                match pending.usage.files {
+                    1 => "file",
+                    _ => "files",
+                }
+            ),
+        ];
+        body.extend(warning.map(String::from));
+        body.push(String::new());
+        body.push(String::from("[y] confirm  [any other key] cancel"));
+
+        let inner_width = body
+            .iter()
+            .chain([&title.to_string()])
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(width.saturating_sub(4));
+        let fit = |line: &str| {
+            let line = line.chars().take(inner_width).collect::<String>();
+            format!("{:<inner_width$}", line)
+        };
+
+        let mut dialog = vec![format!("┌ {} ┐", fit(title))];
+        dialog.extend(body.iter().map(|line| format!("│ {} │", fit(line))));
+        dialog.push(format!("└{}┘", "─".repeat(inner_width + 2)));
+
+        let top = 1 + list_height.saturating_sub(dialog.len()) / 2;
+        let indent = " ".repeat(width.saturating_sub(inner_width + 4) / 2);
+        for (row, line) in rows.iter_mut().skip(top).zip(dialog) {
+            let line = match pending.mode {
+                DeleteMode::Trash => line.bright_yellow(),
+                DeleteMode::Permanent => line.bright_red(),
+            };
+            *row = format!("{}{}", indent, line.bold());
+        }
+    }
+}
+
+/// Browses the changes between two scans.
+pub struct DiffNavigator {
+    root: DiffNode,
+    size_mode: SizeMode,
+    path: Vec<usize>,
+    selected: Vec<usize>,
+    scroll: usize,
+    message: Option<String>,
+}
+
+impl DiffNavigator {
+    pub fn new(root: DiffNode, size_mode: SizeMode) -> Self {
+        Self {
+            root,
+            size_mode,
+            path: Vec::new(),
+            selected: vec![0],
+            scroll: 0,
+            message: None,
+        }
+    }
+
+    pub fn run(mut self) -> anyhow::Result<()> {
+        let _guard = TerminalGuard::enter()?;
+        let mut stdout = io::stdout();
+
+        loop {
+            self.draw(&mut stdout)?;
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    self.message = None;
+                    if is_quit(key) {
+                        return Ok(());
+                    }
+                    self.handle_key(key);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        let len = self.current().children.len();
+        if move_selection(self.selected.last_mut().unwrap(), len, key.code) {
+            return;
+        }
+
+        let selected = *self.selected.last().unwrap();
+        match key.code {
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => {
+                match self.current().children.get(selected) {
+                    Some(child) if !child.children.is_empty() => {
+                        self.path.push(selected);
+                        self.selected.push(0);
+                        self.scroll = 0;
+                    }
+                    Some(_) => self.message = Some(String::from("Nothing changed inside")),
+                    None => {}
+                }
+            }
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => {
+                if self.path.pop().is_some() {
+                    self.selected.pop();
+                    self.scroll = 0;
+                } else {
+                    self.message = Some(String::from("Already at the top of the diff"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn current(&self) -> &DiffNode {
+        node_at(&self.root, &self.path)
+    }
+
+    fn current_path(&self) -> PathBuf {
+        path_at(&self.root, &self.path)
+    }
+
+    fn render_row(&self, child: &DiffNode, is_selected: bool) -> String {
+        let name = match child.kind {
+            NodeKind::Directory => format!("{}/", child.name).bright_cyan(),
+            NodeKind::File | NodeKind::Symlink => child.name.bright_white(),
+        };
+        let row = format!(
+            " {:>11} {:<9} {:>10} → {:<10} {}",
+            format_change(child.change(self.size_mode)),
+            render_diff_status(child.status(self.size_mode)),
+            render_optional_size(child.old.map(|usage| usage.get(self.size_mode))),
+            render_optional_size(child.new.map(|usage| usage.get(self.size_mode))),
+            name
+        );
+        match is_selected {
+            true => row.reversed().to_string(),
+            false => row,
+        }
+    }
+
+    fn draw(&mut self, stdout: &mut io::Stdout) -> anyhow::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let list_height = height.saturating_sub(4);
+        let selected = *self.selected.last().unwrap();
+        scroll_to(&mut self.scroll, selected, list_height);
+
+        let current = self.current();
+        let path = self.current_path();
+        let mut rows = list_rows(
+            format!(" df2 diff {}", path.display()),
+            &current.children,
+            self.scroll,
+            selected,
+            list_height,
+            " (no changes)",
+            |child, is_selected| self.render_row(child, is_selected),
+        );
+
+        let keys = ["↑↓/jk move", "⏎ open", "⌫ up", "q quit"];
+        let status = match &self.message {
+            Some(message) => message.bright_yellow().to_string(),
+            None => format!(
+                " {}  {} → {} ({})  {}",
+                path.display(),
+                ByteSize::b(current.old_size(self.size_mode)),
+                ByteSize::b(current.new_size(self.size_mode)),
+                format_change(current.change(self.size_mode)),
+                keys.join("  ").dimmed()
+            ),
+        };
+        rows.push(status);
+
+        write_rows(stdout, &rows, width, height)
+    }
+}
+
+// Trees the navigators browse one directory at a time
+trait Browsable: Sized {
+    fn name(&self) -> &str;
+    fn children(&self) -> &[Self];
+}
+
+impl Browsable for ScanNode {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn children(&self) -> &[Self] {
+        &self.children
+    }
+}
+
+impl Browsable for DiffNode {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn children(&self) -> &[Self] {
+        &self.children
+    }
+}
+
+// Entry reached by following child indices from the root
+fn node_at<'a, T: Browsable>(root: &'a T, path: &[usize]) -> &'a T {
+    path.iter()
+        .fold(root, |node, &index| &node.children()[index])
+}
+
+fn path_at<T: Browsable>(root: &T, path: &[usize]) -> PathBuf {
+    let mut node = root;
+    let mut dir = PathBuf::from(node.name());
+    for &index in path {
+        node = &node.children()[index];
+        dir.push(node.name());
+    }
+    dir
+}
+
+// Scrolls the list just far enough to show the selected row
+fn scroll_to(scroll: &mut usize, selected: usize, list_height: usize) {
+    if selected < *scroll {
+        *scroll = selected;
+    } else if selected >= *scroll + list_height {
+        *scroll = selected + 1 - list_height;
+    }
+}
+
+// Title bar followed by the visible part of the list, padded to its height
+fn list_rows<T>(
+    title: String,
+    items: &[T],
+    scroll: usize,
+    selected: usize,
+    list_height: usize,
+    empty: &str,
+    render_row: impl Fn(&T, bool) -> String,
+) -> Vec<String> {
+    let mut rows = vec![title.bold().reversed().to_string()];
+    rows.extend(
+        items
+            .iter()
+            .enumerate()
+            .skip(scroll)
+            .take(list_height)
+            .map(|(i, item)| render_row(item, i == selected)),
+    );
+    if items.is_empty() {
+        rows.push(empty.dimmed().to_string());
+    }
+    rows.resize(list_height + 2, String::new());
+    rows
+}
+
+fn write_rows(
+    stdout: &mut io::Stdout,
+    rows: &[String],
+    width: usize,
+    height: usize,
+) -> anyhow::Result<()> {
+    queue!(stdout, MoveTo(0, 0), Clear(ClearType::All))?;
+    let mut lines = Lines::from_colored_multiline_string(&rows.join("\n"));
+    lines.truncate_lines(width);
+    for (i, line) in lines.iter().take(height).enumerate() {
+        queue!(stdout, MoveTo(0, i as u16))?;
+        write!(stdout, "{}", line.render())?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+fn is_quit(key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+// Handles the keys that move the selection within a list of `len` items
+fn move_selection(selected: &mut usize, len: usize, code: KeyCode) -> bool {
+    let page = terminal::size().map_or(10, |(_, height)| height as usize / 2);
+    let last = len.saturating_sub(1);
+    *selected = match code {
+        KeyCode::Up | KeyCode::Char('k') => selected.saturating_sub(1),
+        KeyCode::Down | KeyCode::Char('j') => (*selected + 1).min(last),
+        KeyCode::PageUp => selected.saturating_sub(page),
+        KeyCode::PageDown => (*selected + page).min(last),
+        KeyCode::Home | KeyCode::Char('g') => 0,
+        KeyCode::End | KeyCode::Char('G') => last,
+        _ => return false,
+    };
+    true
+}
+
+fn remove_path(path: &Path) -> anyhow::Result<()> {
+    match fs::symlink_metadata(path)?.is_dir() {
+        true => fs::remove_dir_all(path)?,
+        false => fs::remove_file(path)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_job::file_util::EntryTimes;
+    use crate::scan_job::scan_tree::{dir, file};
+    use clap::Parser;
+
+    fn dated_file(name: &str, size: u64, mtime: i64) -> ScanNode {
+        let times = EntryTimes {
+            mtime: Some(mtime),
+            atime: None,
+        };
+        ScanNode {
+            mtime: times.mtime,
+            newest: times,
+            ..file(name, size)
+        }
+    }
+
+    #[test]
+    fn removing_an_entry_updates_the_directories_above_it() {
+        let tree = dir(
+            "/root",
+            vec![
+                dir(
+                    "a",
+                    vec![dir(
+                        "b",
+                        vec![dated_file("x", 10, 50), dated_file("y", 5, 20)],
+                    )],
+                ),
+                dated_file("z", 1, 10),
+            ],
+        );
+        let args = ScanJobArgs::parse_from(["df2"]);
+        let mut navigator = Navigator::new(args, tree, false, true);
+        navigator.descend();
+        navigator.descend();
+        assert_eq!(navigator.current_path(), Path::new("/root/a/b"));
+
+        navigator.remove_selected();
+        assert_eq!(navigator.current().children.len(), 1);
+        assert_eq!(*navigator.selected.last().unwrap(), 0);
+        let a = &navigator.root.children[0];
+        let root = &navigator.root;
+        assert_eq!((a.usage.apparent, a.usage.files), (5, 1));
+        assert_eq!((root.usage.apparent, root.usage.files), (6, 2));
+        assert_eq!(a.newest.mtime, Some(20));
+        assert_eq!(root.newest.mtime, Some(20));
+    }
+}
@@ -0,0 +1,31 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs;
+
+static USERS: Lazy<HashMap<u32, String>> = Lazy::new(|| read_names("/etc/passwd"));
+static GROUPS: Lazy<HashMap<u32, String>> = Lazy::new(|| read_names("/etc/group"));
+
+// Both files are `name:password:id:...` lines
+fn read_names(file: &str) -> HashMap<u32, String> {
+    fs::read_to_string(file)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse().ok()?;
+            Some((id, name.to_string()))
+        })
+        .collect()
+}
+
+/// Name of a user, or its uid if it has no entry in /etc/passwd.
+pub fn user_name(uid: u32) -> String {
+    USERS.get(&uid).cloned().unwrap_or_else(|| uid.to_string())
+}
+
+/// Name of a group, or its gid if it has no entry in /etc/group.
+pub fn group_name(gid: u32) -> String {
+    GROUPS.get(&gid).cloned().unwrap_or_else(|| gid.to_string())
+}
@@ -0,0 +1,133 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    exclude: GlobSet,
+    include: Option<GlobSet>,
+    // The patterns behind both sets, to tell filters apart
+    patterns: String,
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        Self {
+            exclude: GlobSet::empty(),
+            include: None,
+            patterns: String::new(),
+        }
+    }
+}
+
+impl PathFilter {
+    pub fn new(
+        exclude: &[String],
+        exclude_from: &[PathBuf],
+        include: &[String],
+    ) -> anyhow::Result<Self> {
+        let mut exclude_patterns = exclude.to_vec();
+        for file in exclude_from {
+            let contents = fs::read_to_string(file).map_err(|e| {
+                anyhow::anyhow!("Error reading exclude file '{}': {}", file.display(), e)
+            })?;
+            exclude_patterns.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+
+        Ok(Self {
+            patterns: format!("{:?} {:?}", exclude_patterns, include),
+            exclude: build_glob_set(&exclude_patterns)?,
+            include: match include.is_empty() {
+                true => None,
+                false => Some(build_glob_set(include)?),
+            },
+        })
+    }
+
+    pub fn patterns(&self) -> &str {
+        &self.patterns
+    }
+
+    pub fn has_includes(&self) -> bool {
+        self.include.is_some()
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        matches(&self.exclude, path)
+    }
+
+    pub fn is_included(&self, path: &Path) -> bool {
+        match &self.include {
+            Some(include) => matches(include, path),
+            None => true,
+        }
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+fn matches(set: &GlobSet, path: &Path) -> bool {
+    if set.is_empty() {
+        return false;
+    }
+
+    set.is_match(path) || path.file_name().is_some_and(|name| set.is_match(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(exclude: &[&str], include: &[&str]) -> PathFilter {
+        let strings = |globs: &[&str]| globs.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        PathFilter::new(&strings(exclude), &[], &strings(include)).unwrap()
+    }
+
+    #[test]
+    fn excludes_by_path_or_name() {
+        let filter = filter(&["*.log", "**/target"], &[]);
+        assert!(filter.is_excluded(Path::new("./a/b.log")));
+        assert!(filter.is_excluded(Path::new("./crate/target")));
+        assert!(!filter.is_excluded(Path::new("./crate/src")));
+        assert!(filter.is_included(Path::new("./crate/src")));
+        assert!(!filter.has_includes());
+    }
+
+    #[test]
+    fn includes_only_matching_paths() {
+        let filter = filter(&[], &["*.rs"]);
+        assert!(filter.has_includes());
+        assert!(filter.is_included(Path::new("src/main.rs")));
+        assert!(!filter.is_included(Path::new("README.md")));
+        assert!(!filter.is_excluded(Path::new("README.md")));
+    }
+
+    #[test]
+    fn reads_exclude_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("excludes");
+        fs::write(&file, "# comment\n\n  *.tmp  \nnode_modules\n").unwrap();
+        let filter = PathFilter::new(&[], &[file], &[]).unwrap();
+        assert!(filter.is_excluded(Path::new("a/b.tmp")));
+        assert!(filter.is_excluded(Path::new("web/node_modules")));
+        assert!(!filter.is_excluded(Path::new("# comment")));
+    }
+
+    #[test]
+    fn rejects_invalid_globs() {
+        assert!(PathFilter::new(&["a[".to_string()], &[], &[]).is_err());
+        assert!(PathFilter::new(&[], &[PathBuf::from("/nonexistent/df2")], &[]).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use super::file_util::{EntryTimes, Usage};
+use super::path_filter::PathFilter;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+const CACHE_VERSION: u32 = 3;
+
+// What the files directly inside a directory add up to. Files with more than
+// one link aren't included, since they're counted once across directories.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileSummary {
+    pub usage: Usage,
+    pub excluded: Usage,
+    pub newest: EntryTimes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    dev: u64,
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    options: u64,
+    pub dirs: Vec<String>,
+    // Files with more than one link, which are looked at on every scan
+    pub links: Vec<String>,
+    pub files: FileSummary,
+}
+
+impl CacheEntry {
+    pub fn new(
+        metadata: &fs::Metadata,
+        options: u64,
+        dirs: Vec<String>,
+        links: Vec<String>,
+        files: FileSummary,
+    ) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            options,
+            dirs,
+            links,
+            files,
+        }
+    }
+
+    fn is_valid(&self, metadata: &fs::Metadata, options: u64) -> bool {
+        self.dev == metadata.dev()
+            && self.ino == metadata.ino()
+            && self.mtime == metadata.mtime()
+            && self.mtime_nsec == metadata.mtime_nsec()
+            && self.options == options
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<String, CacheEntry>,
+}
+
+/// Tells apart scans whose files can be excluded differently.
+pub fn options_key(root: &str, filter: &PathFilter) -> u64 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(root.as_bytes());
+    hasher.update(&[0]);
+    hasher.update(filter.patterns().as_bytes());
+    let hash = hasher.finalize();
+    u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+}
+
+// Directories keyed by absolute path, with the names of their subdirectories
+// and what the files directly inside of them add up to. Unchanged directories
+// are neither read nor have their files looked at again, so a file written to
+// in place keeps its old size until something in its directory changes.
+// Each scan root has its own file, which is only rewritten if anything below
+// the root changed.
+#[derive(Debug, Default)]
+pub struct ScanCache {
+    dir: Option<PathBuf>,
+    previous: DashMap<String, CacheEntry>,
+    current: DashMap<String, CacheEntry>,
+    roots: Mutex<Vec<PathBuf>>,
+    changed: AtomicBool,
+}
+
+impl ScanCache {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn load() -> Self {
+        Self::in_dir(cache_dir())
+    }
+
+    fn in_dir(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_root(&self, root: &Path) {
+        let mut roots = self.roots.lock().unwrap();
+        if roots.iter().any(|r| r == root) {
+            return;
+        }
+        roots.push(root.to_path_buf());
+
+        let entries = self
+            .root_file(root)
+            .and_then(|file| fs::File::open(file).ok())
+            .and_then(|file| serde_json::from_reader::<_, CacheFile>(BufReader::new(file)).ok())
+            .filter(|cache| cache.version == CACHE_VERSION)
+            .map(|cache| cache.entries)
+            .unwrap_or_default();
+        for (path, entry) in entries {
+            self.previous.insert(path, entry);
+        }
+    }
+
+    pub fn lookup(&self, path: &Path, metadata: &fs::Metadata, options: u64) -> Option<CacheEntry> {
+        let key = path.to_string_lossy();
+        let entry = match self.current.get(key.as_ref()) {
+            Some(entry) => entry.clone(),
+            None => self.previous.get(key.as_ref())?.clone(),
+        };
+        if !entry.is_valid(metadata, options) {
+            return None;
+        }
+
+        self.current.insert(key.to_string(), entry.clone());
+        Some(entry)
+    }
+
+    pub fn record(&self, path: &Path, entry: CacheEntry) {
+        let key = path.to_string_lossy().to_string();
+        if self
+            .previous
+            .get(&key)
+            .is_none_or(|previous| *previous != entry)
+        {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        self.current.insert(key, entry);
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+
+        for root in self.roots.lock().unwrap().iter() {
+            let entries = self
+                .current
+                .iter()
+                .filter(|entry| Path::new(entry.key()).starts_with(root))
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect::<HashMap<_, _>>();
+            // Directories that are gone leave behind entries that weren't visited
+            let previous = self
+                .previous
+                .iter()
+                .filter(|entry| Path::new(entry.key()).starts_with(root))
+                .count();
+            if !self.changed.load(Ordering::Relaxed) && previous == entries.len() {
+                continue;
+            }
+
+            fs::create_dir_all(dir)?;
+            let mut tmp_file = tempfile::NamedTempFile::new_in(dir)?;
+            let mut writer = BufWriter::new(tmp_file.as_file_mut());
+            serde_json::to_writer(
+                &mut writer,
+                &CacheFile {
+                    version: CACHE_VERSION,
+                    entries,
+                },
+            )?;
+            writer.flush()?;
+            drop(writer);
+            tmp_file.persist(self.root_file(root).unwrap())?;
+        }
+        Ok(())
+    }
+
+    fn root_file(&self, root: &Path) -> Option<PathBuf> {
+        let hash = blake3::hash(root.as_os_str().as_bytes()).to_hex();
+        Some(self.dir.as_ref()?.join(format!("{}.json", &hash[..16])))
+    }
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_home.join("df2"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(metadata: &fs::Metadata, options: u64, dirs: &[&str]) -> CacheEntry {
+        let dirs = dirs.iter().map(|d| d.to_string()).collect();
+        CacheEntry::new(metadata, options, dirs, vec![], FileSummary::default())
+    }
+
+    #[test]
+    fn entries_expire_when_the_directory_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ScanCache::in_memory();
+        let metadata = fs::metadata(dir.path()).unwrap();
+        cache.record(dir.path(), entry(&metadata, 1, &["a"]));
+        let cached = cache.lookup(dir.path(), &metadata, 1).unwrap();
+        assert_eq!(cached.dirs, vec!["a".to_string()]);
+        assert!(cache.lookup(dir.path(), &metadata, 2).is_none());
+
+        fs::write(dir.path().join("b"), "b").unwrap();
+        let metadata = fs::metadata(dir.path()).unwrap();
+        assert!(cache.lookup(dir.path(), &metadata, 1).is_none());
+
+        // Another directory that ends up at the same path
+        let other = tempfile::tempdir().unwrap();
+        let other_metadata = fs::metadata(other.path()).unwrap();
+        cache.record(dir.path(), entry(&metadata, 1, &[]));
+        assert!(cache.lookup(dir.path(), &other_metadata, 1).is_none());
+    }
+
+    #[test]
+    fn saves_each_root_only_when_it_changed() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let metadata = fs::metadata(root.path()).unwrap();
+        let load = || {
+            let cache = ScanCache::in_dir(Some(cache_dir.path().to_path_buf()));
+            cache.add_root(root.path());
+            cache
+        };
+
+        let cache = load();
+        cache.record(root.path(), entry(&metadata, 1, &["a"]));
+        cache.save().unwrap();
+        let file = cache.root_file(root.path()).unwrap();
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 1);
+
+        let cache = load();
+        assert!(cache.lookup(root.path(), &metadata, 1).is_some());
+        cache.record(root.path(), entry(&metadata, 1, &["a"]));
+        fs::remove_file(&file).unwrap();
+        cache.save().unwrap();
+        assert!(!file.exists());
+
+        cache.record(root.path(), entry(&metadata, 1, &["a", "b"]));
+        cache.save().unwrap();
+        assert!(file.exists());
+    }
+}
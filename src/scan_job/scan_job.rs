@@ -1,16 +1,26 @@
+use super::breakdown::{Breakdown, BreakdownGroup};
 use super::file_util::get_dir_size;
-use super::file_util::ItemView;
-use super::line_item::{ItemType, LineItem};
+use super::file_util::{
+    AtomicTimes, AtomicUsage, DirectoryScanProgress, EntryTimes, ItemView, LinkClaims, ScanOptions,
+    ScanStats, SortKey, SortOrder, Usage,
+};
+use super::line_item::{Growth, ItemType, LineItem};
 use super::lines_component::LinesComponent;
+use super::scan_cache::ScanCache;
 use super::scan_job_args::ScanJobArgs;
+use super::scan_tree::{NodeKind, ScanNode};
+use super::top_files::TopFiles;
+use bytesize::ByteSize;
 use colored::Colorize;
 use once_cell::sync::Lazy;
 use prettytable::format::TableFormat;
 use prettytable::*;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 use superconsole::components::bordering::{Bordered, BorderedSpec};
@@ -18,7 +28,7 @@ use superconsole::components::DrawVertical;
 use superconsole::style::ContentStyle;
 use superconsole::{Component, Dimensions, DrawMode, Lines, SuperConsole};
 
-static TABLE_FROMAT: Lazy<TableFormat> = Lazy::new(|| {
+pub static TABLE_FROMAT: Lazy<TableFormat> = Lazy::new(|| {
     format::FormatBuilder::new()
         .column_separator(' ')
         .separators(&[], format::LineSeparator::new(' ', ' ', ' ', ' '))
@@ -26,6 +36,10 @@ static TABLE_FROMAT: Lazy<TableFormat> = Lazy::new(|| {
         .build()
 });
 
+const CHILD_BAR_WIDTH: usize = 40;
+const CHILD_BREAKDOWN_LIMIT: usize = 10;
+const WATCH_TABLE_ROWS: usize = 20;
+
 static EMPTY_LINE: Lazy<LinesComponent> =
     Lazy::new(|| LinesComponent::new(Lines::from_multiline_string("\n", ContentStyle::default())));
 
@@ -54,8 +68,30 @@ pub fn color_portion(str: String, portion: PortionColor) -> String {
 #[derive(Debug)]
 pub struct ScanJob {
     scan_view: Arc<Mutex<Vec<Arc<ItemView>>>>,
+    stats: Arc<ScanStats>,
     args: ScanJobArgs,
-    pub result: Arc<Mutex<Vec<String>>>,
+    options: ScanOptions,
+    breakdown: Option<Breakdown>,
+    child_breakdowns: Mutex<Vec<ChildBreakdown>>,
+    started: Instant,
+    previous_pass: Option<PreviousPass>,
+    // Updated from a tree that only keeps track of the files that were counted
+    live: bool,
+}
+
+/// Sizes of the children of the root at the end of a --watch pass.
+#[derive(Debug)]
+pub struct PreviousPass {
+    started: Instant,
+    sizes: HashMap<String, u64>,
+}
+
+// Groups of a child directory, for ordered breakdowns
+#[derive(Debug)]
+struct ChildBreakdown {
+    path: String,
+    usage: Usage,
+    groups: Vec<BreakdownGroup>,
 }
 
 impl Component for ScanJob {
@@ -66,8 +102,17 @@ impl Component for ScanJob {
             .fold(0, |acc, item| acc + item.size_snapshot);
 
         if total_size == 0 {
+            let excluded = match self.live {
+                true => 0,
+                false => self.stats.excluded.load().get(self.args.size_mode()),
+            };
+            let message = match (excluded, self.breakdown) {
+                (0, Some(breakdown)) => format!("Nothing to group by {}", breakdown.label()),
+                (0, None) => String::from("Directory is empty"),
+                _ => format!("All items were excluded ({})", ByteSize::b(excluded)),
+            };
             return Ok(Lines::from_multiline_string(
-                "Directory is empty",
+                &message,
                 ContentStyle::default(),
             ));
         }
@@ -89,9 +134,11 @@ impl Component for ScanJob {
         let mut draw_vertical = DrawVertical::new(dimensions);
         if let DrawMode::Normal = mode {
             drew_something = true;
-            let mut bordered_spec = BorderedSpec::default();
-            bordered_spec.left = None;
-            bordered_spec.right = None;
+            let bordered_spec = BorderedSpec {
+                left: None,
+                right: None,
+                ..Default::default()
+            };
             draw_vertical.draw(&Bordered::new(item_table, bordered_spec), mode)?;
         }
 
@@ -101,30 +148,95 @@ impl Component for ScanJob {
 
         draw_vertical.draw(&stacked_bar, mode)?;
 
+        if mode == DrawMode::Final {
+            let sections = [
+                ("By directory", self.render_child_breakdowns()),
+                (
+                    "Largest files",
+                    self.render_top_files().filter(|_| !self.live),
+                ),
+            ];
+            for (title, table) in sections {
+                if let Some(table) = table {
+                    draw_vertical.draw(&*EMPTY_LINE, mode)?;
+                    draw_vertical
+                        .draw(&LinesComponent::from_str(&title.bold().to_string()), mode)?;
+                    draw_vertical.draw(&LinesComponent::from_str(&table.to_string()), mode)?;
+                }
+            }
+        }
+
         Ok(draw_vertical.finish())
     }
 }
 
 impl ScanJob {
-    pub fn new(args: ScanJobArgs) -> Self {
+    pub fn new(args: ScanJobArgs, options: ScanOptions) -> Self {
         Self {
             scan_view: Arc::new(Mutex::new(Vec::new())),
+            stats: Arc::new(ScanStats {
+                top_files: TopFiles::new(args.top_files.unwrap_or(0), args.size_mode()),
+                ..Default::default()
+            }),
+            breakdown: args.breakdown(),
+            child_breakdowns: Mutex::new(Vec::new()),
+            started: Instant::now(),
+            previous_pass: None,
+            live: false,
             args,
-            result: Arc::new(Mutex::new(Vec::new())),
+            options,
         }
     }
 
-    fn render_size_table(
-        &self,
-        line_items: &Vec<LineItem>,
-        total_size: u64,
-        is_final: bool,
-    ) -> Table {
+    pub fn with_breakdown(mut self, breakdown: Option<Breakdown>) -> Self {
+        self.breakdown = breakdown;
+        self
+    }
+
+    pub fn with_previous_pass(mut self, previous_pass: Option<PreviousPass>) -> Self {
+        self.previous_pass = previous_pass;
+        self
+    }
+
+    /// Leaves out the largest files, hard-link savings and excluded size, which
+    /// a live tree doesn't keep up to date.
+    pub fn live(mut self) -> Self {
+        self.live = true;
+        self
+    }
+
+    /// Hard links counted by the scan, for rescanning parts of its tree.
+    pub fn link_claims(&self) -> Arc<LinkClaims> {
+        self.stats.hard_links.clone()
+    }
+
+    pub fn finish_pass(&self) -> PreviousPass {
+        PreviousPass {
+            started: self.started,
+            sizes: self
+                .line_items()
+                .into_iter()
+                .map(|item| (item.path, item.size_snapshot))
+                .collect(),
+        }
+    }
+
+    fn render_size_table(&self, line_items: &[LineItem], total_size: u64, is_final: bool) -> Table {
         let mut table = Table::new();
         table.set_format(*TABLE_FROMAT);
 
+        // Watching keeps the children that changed the most in view, with
+        // their growth once they're scanned
+        let watching = self.args.watch.is_some() || self.previous_pass.is_some();
+        if watching && !is_final {
+            for item in most_changed(line_items, WATCH_TABLE_ROWS) {
+                table.add_row(item.render_progress_row(total_size, is_final));
+            }
+            return table;
+        }
+
         let mut remaining_list_items = match is_final {
-            true => u64::MAX,
+            true => usize::MAX,
             false => 6,
         };
         for item in line_items.iter().rev() {
@@ -143,11 +255,64 @@ impl ScanJob {
         table
     }
 
+    fn render_top_files(&self) -> Option<Table> {
+        let top_files = self.stats.top_files.sorted();
+        if top_files.is_empty() {
+            return None;
+        }
+
+        let mut table = Table::new();
+        table.set_format(*TABLE_FROMAT);
+        for file in &top_files {
+            table.add_row(LineItem::render_top_file_row(
+                file,
+                self.args.size_mode(),
+                self.args.secondary_size_mode(),
+            ));
+        }
+        Some(table)
+    }
+
+    // One bar per child directory, colored by group rank so that the colors
+    // match the legend of the stacked bar
+    fn render_child_breakdowns(&self) -> Option<Table> {
+        let child_breakdowns = self.child_breakdowns.lock().unwrap();
+        if child_breakdowns.is_empty() {
+            return None;
+        }
+
+        let size_mode = self.args.size_mode();
+        let mut table = Table::new();
+        table.set_format(*TABLE_FROMAT);
+        for child in child_breakdowns.iter() {
+            let total = child.usage.get(size_mode);
+            let mut remaining_width = CHILD_BAR_WIDTH;
+            let mut bar_str = String::new();
+            for (group, portion) in child.groups.iter().zip(PortionColor::iter()) {
+                let width = match portion {
+                    PortionColor::PortionLast => remaining_width,
+                    _ => {
+                        (group.usage.get(size_mode) as f64 / total as f64 * CHILD_BAR_WIDTH as f64)
+                            .floor() as usize
+                    }
+                };
+                bar_str.push_str(&color_portion("█".repeat(width), portion));
+                remaining_width = remaining_width.saturating_sub(width);
+            }
+            table.add_row(LineItem::render_child_breakdown_row(
+                &child.path,
+                total,
+                &bar_str,
+            ));
+        }
+        Some(table)
+    }
+
     fn render_stacked_bar(
         &self,
         dimensions: Dimensions,
         mode: DrawMode,
-        line_items: &Vec<LineItem>,
+        line_items: &[LineItem],
         total_size: u64,
     ) -> anyhow::Result<Lines> {
         let mut bar_str = String::new();
@@ -158,12 +323,22 @@ impl ScanJob {
 
         let mut remaining_width = total_width;
         let len = line_items.len();
-        let mut did_aggregate_other = false;
+        // Only the smallest items at the end can be folded into "Other"
+        let is_ordered = self
+            .breakdown
+            .is_some_and(|breakdown| breakdown.is_ordered())
+            || (self.args.sort, self.args.sort_order()) != (SortKey::Size, SortOrder::Desc);
         let mut legend_table = Table::new();
         legend_table.set_format(*TABLE_FROMAT);
-        let mut other_size = total_size;
-        for (i, mut portion) in PortionColor::iter().enumerate() {
-            if i == len || did_aggregate_other {
+        let secondary_mode = self.args.secondary_size_mode();
+        if let Some(secondary_mode) = secondary_mode {
+            legend_table.add_row(LineItem::render_legend_header(
+                self.args.size_mode(),
+                secondary_mode,
+            ));
+        }
+        for (i, portion) in PortionColor::iter().enumerate() {
+            if i == len {
                 break;
             }
 
@@ -172,52 +347,73 @@ impl ScanJob {
             let proportion = item.size_snapshot as f64 / total_size as f64;
             let item_width = (proportion * total_width as f64).floor() as usize;
             let is_last = portion == PortionColor::PortionLast || i == len - 1;
-            let width = if is_last || item_width == 0 {
-                did_aggregate_other = i != len - 1;
-                if did_aggregate_other {
-                    portion = PortionColor::PortionLast;
-                }
-                remaining_width
-            } else {
-                item_width
-            };
-
-            let portion_str = "█".repeat(width);
-            let portion_str = color_portion(portion_str, portion);
-            bar_str.push_str(&portion_str);
-            remaining_width = remaining_width.saturating_sub(width);
-
-            if did_aggregate_other && self.args.list_items && mode == DrawMode::Final {
-                break;
+            // Ordered breakdowns always show every group, however small
+            if !is_last && (item_width > 0 || is_ordered) {
+                bar_str.push_str(&color_portion("█".repeat(item_width), portion));
+                remaining_width = remaining_width.saturating_sub(item_width);
+                legend_table.add_row(item.render_legend_row(i, portion, false));
+                continue;
             }
 
-            if did_aggregate_other {
-                legend_table.add_row(LineItem::render_legend_row_other(
-                    &color_portion(String::from("Other"), PortionColor::PortionLast),
-                    other_size,
-                ));
-            } else {
-                other_size -= item.size_snapshot;
-                let (row, show_index) = item.render_legend_row(i, portion, did_aggregate_other);
-                if mode == DrawMode::Final && show_index {
-                    self.result.lock().unwrap().push(item.path.clone());
+            // The last portion takes up the rest of the bar, with every item left
+            let rest = &line_items[..=j];
+            let other;
+            let (item, portion) = match rest {
+                [item] if item.item_type != ItemType::Other => (item, portion),
+                _ => {
+                    other = LineItem::other(rest);
+                    (&other, PortionColor::PortionLast)
+                }
+            };
+            bar_str.push_str(&color_portion("█".repeat(remaining_width), portion));
+            if self.args.list_items && mode == DrawMode::Final && rest.len() > 1 {
+                for (k, item) in rest.iter().rev().enumerate() {
+                    legend_table.add_row(item.render_legend_row(i + k, portion, true));
                 }
-                legend_table.add_row(row);
+            } else {
+                legend_table.add_row(item.render_legend_row(i, portion, rest.len() > 1));
             }
+            break;
         }
-        if did_aggregate_other && self.args.list_items && mode == DrawMode::Final {
-            for i in PortionColor::iter().count() - 1..line_items.len() {
-                let j = len - i - 1;
-                let item = &line_items[j];
-                let (row, _) = item.render_legend_row(i, PortionColor::PortionLast, true);
-                legend_table.add_row(row);
-            }
+        for mount in self.stats.skipped_mounts.lock().unwrap().iter() {
+            legend_table.add_row(LineItem::render_legend_row_mount(
+                mount,
+                secondary_mode.is_some(),
+            ));
         }
+        let total_label = match self.breakdown {
+            Some(breakdown) => format!("{} by {}", self.args.directory, breakdown.label()),
+            None => self.args.directory.clone(),
+        };
         legend_table.add_row(LineItem::render_legend_row_other(
-            &self.args.directory.bright_white().bold().to_string(),
+            &total_label.bright_white().bold().to_string(),
             total_size,
+            secondary_mode.map(|_| {
+                line_items
+                    .iter()
+                    .filter_map(|item| item.secondary_size_snapshot)
+                    .sum()
+            }),
         ));
 
+        let hard_link_savings = self.stats.hard_link_savings.load();
+        if hard_link_savings.get(self.args.size_mode()) > 0 && !self.live {
+            legend_table.add_row(LineItem::render_legend_row_other(
+                &"hard links (deduplicated)".dimmed().to_string(),
+                hard_link_savings.get(self.args.size_mode()),
+                secondary_mode.map(|mode| hard_link_savings.get(mode)),
+            ));
+        }
+
+        let excluded = self.stats.excluded.load();
+        if excluded.get(self.args.size_mode()) > 0 && !self.live {
+            legend_table.add_row(LineItem::render_legend_row_other(
+                &"excluded".dimmed().to_string(),
+                excluded.get(self.args.size_mode()),
+                secondary_mode.map(|mode| excluded.get(mode)),
+            ));
+        }
+
         let mut draw_vertical = DrawVertical::new(dimensions);
         draw_vertical.draw(&LinesComponent::from_str(&legend_table.to_string()), mode)?;
         draw_vertical.draw(&*EMPTY_LINE, mode)?;
@@ -225,33 +421,114 @@ impl ScanJob {
         Ok(draw_vertical.finish())
     }
 
+    // Items too small to be worth a row of their own with --min-size or --min-percent
+    fn is_below_threshold(&self, size: u64, total_size: u64) -> bool {
+        let percent = size as f64 / total_size as f64 * 100.0;
+        self.args.min_size.is_some_and(|min| size < min.as_u64())
+            || self.args.min_percent.is_some_and(|min| percent < min)
+    }
+
     fn pre_render(&self) -> Vec<LineItem> {
-        let mut items = self
-            .scan_view
+        let mut items = self.line_items();
+        match self.breakdown {
+            Some(breakdown) if breakdown.is_ordered() => {
+                items.sort_by_key(|item| std::cmp::Reverse(item.rank))
+            }
+            _ => {
+                // Items still being scanned may yet grow past the threshold
+                let total_size = items.iter().map(|item| item.size_snapshot).sum();
+                let (small, rest): (Vec<_>, Vec<_>) = items.into_iter().partition(|item| {
+                    item.completed_time.is_some()
+                        && self.is_below_threshold(item.size_snapshot, total_size)
+                });
+                items = rest;
+
+                let key = self.args.sort;
+                // Items are drawn last to first
+                items.sort_by(|a, b| match self.args.sort_order() {
+                    SortOrder::Desc => key.compare(a.sort_fields(), b.sort_fields()),
+                    SortOrder::Asc => key.compare(b.sort_fields(), a.sort_fields()),
+                });
+                if !small.is_empty() {
+                    items.insert(0, LineItem::other(&small));
+                }
+            }
+        }
+
+        items
+    }
+
+    // Every entry of the view, unsorted
+    fn line_items(&self) -> Vec<LineItem> {
+        let size_mode = self.args.size_mode();
+        let secondary_mode = self.args.secondary_size_mode();
+        let sizes = |usage: Usage| {
+            (
+                usage.get(size_mode),
+                secondary_mode.map(|mode| usage.get(mode)),
+            )
+        };
+
+        let completed = |path: &str, item_type, usage: Usage, times, rank| {
+            let (size_snapshot, secondary_size_snapshot) = sizes(usage);
+            LineItem {
+                path: path.to_string(),
+                item_type,
+                start_time: std::time::Instant::now(),
+                completed_time: Some(std::time::Instant::now()),
+                size_snapshot,
+                secondary_size_snapshot,
+                growth: self.growth(path, size_snapshot),
+                files: usage.files,
+                times,
+                rank,
+            }
+        };
+
+        self.scan_view
             .lock()
             .unwrap()
             .iter()
             .map(|item| match item.as_ref() {
-                ItemView::Directory(path, progress) => LineItem {
-                    path: path.clone(),
-                    item_type: ItemType::Directory,
-                    start_time: progress.start_time,
-                    completed_time: progress.completed_time.lock().unwrap().clone(),
-                    size_snapshot: progress.size.load(Ordering::Acquire),
-                },
-                ItemView::File(path, size) => LineItem {
-                    path: path.clone(),
-                    item_type: ItemType::File,
-                    start_time: std::time::Instant::now(),
-                    completed_time: Some(std::time::Instant::now()),
-                    size_snapshot: *size,
-                },
+                ItemView::Directory(path, progress) => {
+                    let usage = progress.size.load();
+                    let (size_snapshot, secondary_size_snapshot) = sizes(usage);
+                    let completed_time = *progress.completed_time.lock().unwrap();
+                    LineItem {
+                        path: path.clone(),
+                        item_type: ItemType::Directory,
+                        start_time: progress.start_time,
+                        completed_time,
+                        size_snapshot,
+                        secondary_size_snapshot,
+                        growth: completed_time.and_then(|_| self.growth(path, size_snapshot)),
+                        files: usage.files,
+                        times: progress.times.load(),
+                        rank: None,
+                    }
+                }
+                ItemView::File(path, usage, times) => {
+                    completed(path, ItemType::File, *usage, *times, None)
+                }
+                ItemView::Group(group) => completed(
+                    &group.label,
+                    ItemType::Group,
+                    group.usage,
+                    EntryTimes::default(),
+                    group.rank,
+                ),
             })
-            .collect::<Vec<_>>();
-
-        items.sort();
+            .collect()
+    }
 
-        items
+    fn growth(&self, path: &str, size: u64) -> Option<Growth> {
+        let previous = self.previous_pass.as_ref()?;
+        let change = size as i64 - previous.sizes.get(path).copied().unwrap_or(0) as i64;
+        let secs = self.started.duration_since(previous.started).as_secs_f64();
+        Some(Growth {
+            change,
+            bytes_per_sec: change as f64 / secs.max(f64::EPSILON),
+        })
     }
 
     pub fn render_until_flag(&self, console: Arc<Mutex<SuperConsole>>, stop_flag: Arc<AtomicBool>) {
@@ -261,19 +538,266 @@ impl ScanJob {
         }
     }
 
-    pub fn execute<F>(&self, size_cache: Arc<Mutex<HashMap<String, u64>>>, on_error: Arc<F>)
+    pub fn execute<F>(&self, cache: Arc<ScanCache>, on_error: Arc<F>) -> Option<ScanNode>
     where
         F: Fn(String) + Send + Sync + 'static,
     {
         get_dir_size(
             &self.args.directory,
-            size_cache,
-            self.scan_view.clone(),
+            self.options.clone(),
+            self.stats.clone(),
+            cache,
+            Some(self.scan_view.clone()),
             on_error,
+        )
+    }
+
+    pub fn load_tree(&self, node: &ScanNode) {
+        if self.stats.top_files.is_enabled() {
+            offer_top_files(&self.stats.top_files, Path::new(&self.args.directory), node);
+        }
+        self.load_view(node);
+    }
+
+    /// Replaces the entries shown with the children of `node`.
+    pub fn load_view(&self, node: &ScanNode) {
+        if self.breakdown.is_some() {
+            self.load_breakdown(node);
+            return;
+        }
+
+        let now = std::time::Instant::now();
+        let mut scan_view = self.scan_view.lock().unwrap();
+        scan_view.clear();
+        for child in &node.children {
+            let path = Path::new(&self.args.directory)
+                .join(&child.name)
+                .to_string_lossy()
+                .to_string();
+            scan_view.push(Arc::new(match child.kind {
+                NodeKind::Directory => ItemView::Directory(
+                    path,
+                    DirectoryScanProgress {
+                        start_time: now,
+                        completed_time: Arc::new(Mutex::new(Some(now))),
+                        size: Arc::new(AtomicUsage::new(child.usage)),
+                        times: Arc::new(AtomicTimes::new(child.newest)),
+                    },
+                ),
+                NodeKind::File | NodeKind::Symlink => {
+                    ItemView::File(path, child.usage, child.newest)
+                }
+            }));
+        }
+    }
+
+    /// Replaces the items with the groups of the breakdown, if there is one.
+    pub fn load_breakdown(&self, node: &ScanNode) {
+        let Some(breakdown) = self.breakdown else {
+            return;
+        };
+
+        let mut scan_view = self.scan_view.lock().unwrap();
+        scan_view.clear();
+        for group in breakdown.group(node) {
+            scan_view.push(Arc::new(ItemView::Group(group)));
+        }
+
+        // The navigator shows the groups of whichever directory is selected instead
+        if breakdown.is_ordered() && !self.args.interactive_mode {
+            let size_mode = self.args.size_mode();
+            let mut children = node
+                .children
+                .iter()
+                .filter(|child| child.kind == NodeKind::Directory)
+                .map(|child| {
+                    let groups = breakdown.group(child);
+                    let usage = groups.iter().fold(Usage::default(), |mut acc, group| {
+                        acc += group.usage;
+                        acc
+                    });
+                    (child, usage, groups)
+                })
+                .filter(|(_, usage, _)| usage.get(size_mode) > 0)
+                .collect::<Vec<_>>();
+            children.sort_by_key(|(_, usage, _)| std::cmp::Reverse(usage.get(size_mode)));
+            if !self.args.list_items {
+                children.truncate(CHILD_BREAKDOWN_LIMIT);
+            }
+
+            *self.child_breakdowns.lock().unwrap() = children
+                .into_iter()
+                .map(|(child, usage, groups)| ChildBreakdown {
+                    path: Path::new(&self.args.directory)
+                        .join(&child.name)
+                        .to_string_lossy()
+                        .to_string(),
+                    usage,
+                    groups,
+                })
+                .collect();
+        }
+    }
+}
+
+fn offer_top_files(top_files: &TopFiles, path: &Path, node: &ScanNode) {
+    for child in &node.children {
+        let child_path = path.join(&child.name);
+        match child.kind {
+            NodeKind::Directory => offer_top_files(top_files, &child_path, child),
+            NodeKind::File | NodeKind::Symlink => top_files.offer(
+                || child_path.to_string_lossy().to_string(),
+                child.usage,
+                child.mtime,
+            ),
+        }
+    }
+}
+
+// The `count` items that changed the most since the previous pass, biggest
+// change first. Items are in display order, so ties keep the largest ones
+fn most_changed(line_items: &[LineItem], count: usize) -> Vec<&LineItem> {
+    let mut items: Vec<_> = line_items.iter().rev().collect();
+    items.sort_by_key(|item| {
+        std::cmp::Reverse(item.growth.map_or(0, |growth| growth.change.unsigned_abs()))
+    });
+    items.truncate(count);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn loaded_job(args: &[&str], sizes: &[(&str, u64)]) -> ScanJob {
+        let args = ScanJobArgs::parse_from(std::iter::once("df2").chain(args.iter().copied()));
+        let mut root = ScanNode::file(
+            String::from("."),
+            NodeKind::Directory,
+            Usage::default(),
+            None,
+        );
+        for &(name, size) in sizes {
+            let usage = Usage {
+                apparent: size,
+                disk: size,
+                files: 1,
+            };
+            root.usage += usage;
+            root.children.push(ScanNode::file(
+                name.to_string(),
+                NodeKind::File,
+                usage,
+                None,
+            ));
+        }
+        let job = ScanJob::new(args, ScanOptions::default());
+        job.load_view(&root);
+        job
+    }
+
+    fn item(path: &str, size: u64, change: Option<i64>) -> LineItem {
+        LineItem {
+            path: path.to_string(),
+            item_type: ItemType::Directory,
+            start_time: Instant::now(),
+            completed_time: None,
+            size_snapshot: size,
+            secondary_size_snapshot: None,
+            growth: change.map(|change| Growth {
+                change,
+                bytes_per_sec: 0.0,
+            }),
+            files: 0,
+            times: EntryTimes::default(),
+            rank: None,
+        }
+    }
+
+    // In the order they're drawn
+    fn drawn(items: &[LineItem]) -> Vec<(&str, u64)> {
+        items
+            .iter()
+            .rev()
+            .map(|item| (item.path.as_str(), item.size_snapshot))
+            .collect()
+    }
+
+    fn paths(items: Vec<&LineItem>) -> Vec<&str> {
+        items.into_iter().map(|item| item.path.as_str()).collect()
+    }
+
+    #[test]
+    fn watch_rows_keep_the_biggest_changes() {
+        let items = [
+            item("runaway", 1, Some(1000)),
+            item("shrinking", 2, Some(-50)),
+            item("steady", 3, Some(0)),
+            item("large", 4, Some(10)),
+        ];
+        assert_eq!(paths(most_changed(&items, 2)), ["runaway", "shrinking"]);
+        assert_eq!(
+            paths(most_changed(&items, 10)),
+            ["runaway", "shrinking", "large", "steady"]
+        );
+
+        // The first pass has nothing to compare with
+        let items = [item("small", 1, None), item("large", 2, None)];
+        assert_eq!(paths(most_changed(&items, 1)), ["large"]);
+    }
+
+    #[test]
+    fn thresholds_compare_with_the_total() {
+        let job = loaded_job(&["--min-size", "1KiB"], &[]);
+        assert!(job.is_below_threshold(1023, 10_000));
+        assert!(!job.is_below_threshold(1024, 10_000));
+
+        let job = loaded_job(&["--min-percent", "5"], &[]);
+        assert!(job.is_below_threshold(4, 100));
+        assert!(!job.is_below_threshold(5, 100));
+
+        // Either threshold is enough to fold an item
+        let job = loaded_job(&["--min-size", "10", "--min-percent", "50"], &[]);
+        assert!(job.is_below_threshold(9, 10));
+        assert!(job.is_below_threshold(40, 100));
+        assert!(!job.is_below_threshold(60, 100));
+
+        let job = loaded_job(&[], &[]);
+        assert!(!job.is_below_threshold(0, 100));
+    }
+
+    #[test]
+    fn folds_small_items_into_other() {
+        let sizes = [("big", 100), ("tiny", 5), ("mid", 30), ("small", 8)];
+        let items = loaded_job(&["--min-size", "10"], &sizes).pre_render();
+        assert_eq!(
+            drawn(&items),
+            [("./big", 100), ("./mid", 30), ("Other", 13)]
         );
+        assert_eq!(items[0].files, 2);
+
+        let items = loaded_job(&["--min-percent", "50"], &sizes).pre_render();
+        assert_eq!(drawn(&items), [("./big", 100), ("Other", 43)]);
+
+        let items = loaded_job(&[], &sizes).pre_render();
+        assert_eq!(items.len(), 4);
     }
 
-    pub fn get_result(self) -> Vec<String> {
-        Arc::try_unwrap(self.result).unwrap().into_inner().unwrap()
+    #[test]
+    fn folds_small_items_whatever_the_order() {
+        let sizes = [("b-big", 100), ("a-small", 5), ("c-mid", 30), ("d-tiny", 8)];
+        let items = loaded_job(&["--min-size", "10", "--sort", "name"], &sizes).pre_render();
+        assert_eq!(
+            drawn(&items),
+            [("./b-big", 100), ("./c-mid", 30), ("Other", 13)]
+        );
+
+        let args = ["--min-size", "10", "--sort", "name", "--order", "desc"];
+        let items = loaded_job(&args, &sizes).pre_render();
+        assert_eq!(
+            drawn(&items),
+            [("./c-mid", 30), ("./b-big", 100), ("Other", 13)]
+        );
     }
 }
@@ -1,9 +1,115 @@
-use clap::Parser;
+use super::breakdown::{Breakdown, OwnerField, TimeField};
+use super::file_util::{HardLinkPolicy, ScanOptions, SizeMode, SortKey, SortOrder, ThreadCount};
+use super::ignore_rules::IgnoreMode;
+use super::scanner::Scanner;
+use bytesize::ByteSize;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Shorter intervals would rescan back to back without showing any growth
+const MIN_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, ValueEnum)]
+pub enum OutputFormat {
+    /// Colored chart and legend
+    Console,
+    /// Complete scan tree as versioned JSON
+    Json,
+    /// ncdu-compatible JSON dump
+    Ncdu,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal, unless NO_COLOR is set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Find files with identical contents and the space they waste
+    Dupes(DupesArgs),
+    /// Compare a saved scan with a later one, or with a fresh scan of a directory
+    Diff(DiffArgs),
+}
+
+/// Which entries a scan looks at, for the chart and the subcommands alike
+#[derive(Args, Debug, Clone)]
+pub struct ScanArgs {
+    /// Don't cross filesystem boundaries
+    #[arg(short = 'x', long, default_value_t = false)]
+    pub one_file_system: bool,
+
+    /// Leave out files and directories matching a glob (repeatable)
+    #[arg(short, long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Read exclude globs from a file, one per line
+    #[arg(long, value_name = "FILE")]
+    pub exclude_from: Vec<PathBuf>,
+
+    /// Only count files matching a glob, or inside a directory matching it (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files and directories ignored by .gitignore, .ignore and .git/info/exclude
+    #[arg(long, default_value_t = false, conflicts_with = "only_ignored")]
+    pub respect_ignore: bool,
+
+    /// Only count files and directories ignored by .gitignore, .ignore and .git/info/exclude
+    #[arg(long, default_value_t = false, conflicts_with = "respect_ignore")]
+    pub only_ignored: bool,
+
+    /// Number of scan threads, or 'auto' to adjust it to the stat latency while scanning
+    #[arg(short = 'j', long, env = "DF2_THREADS", value_name = "N|auto")]
+    pub threads: Option<ThreadCount>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DupesArgs {
+    /// Directory to search
+    #[arg(default_value = ".")]
+    pub directory: String,
+
+    /// Only show the N sets wasting the most space
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DiffArgs {
+    /// Earlier df2 JSON or ncdu export
+    pub old: PathBuf,
+
+    /// Later export, or a directory to scan now
+    pub new: PathBuf,
+
+    /// Browse the changes in a full-screen navigator
+    #[arg(short, long, default_value_t = false)]
+    pub interactive_mode: bool,
+
+    /// Compare allocated disk blocks instead of apparent file sizes
+    #[arg(short = 'u', long, default_value_t = false)]
+    pub disk_usage: bool,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
 
 /// Calculate the size of a directory
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct ScanJobArgs {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Directory to scan
     #[arg(default_value = ".")]
     pub directory: String,
@@ -12,7 +118,7 @@ pub struct ScanJobArgs {
     #[arg(short, long, default_value_t = false)]
     pub list_items: bool,
 
-    /// Cache the scan results and allow further traversal
+    /// Browse the scan results in a full-screen navigator
     #[arg(short, long, default_value_t = false)]
     pub interactive_mode: bool,
 
@@ -25,6 +131,275 @@ pub struct ScanJobArgs {
     pub full_width: bool,
 
     /// Log all errors
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, default_value_t = false, global = true)]
     pub verbose: bool,
+
+    /// Count allocated disk blocks instead of apparent file sizes
+    #[arg(short = 'u', long, default_value_t = false)]
+    pub disk_usage: bool,
+
+    /// Show apparent and on-disk sizes side by side
+    #[arg(short, long, default_value_t = false)]
+    pub both_sizes: bool,
+
+    /// How to account for files with multiple hard links
+    #[arg(long, value_enum, default_value_t = HardLinkPolicy::First)]
+    pub hard_links: HardLinkPolicy,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Console)]
+    pub output: OutputFormat,
+
+    /// Read a previous df2 JSON or ncdu export instead of scanning
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "exclude",
+            "exclude_from",
+            "include",
+            "respect_ignore",
+            "only_ignored",
+            "one_file_system",
+            "hard_links",
+        ]
+    )]
+    pub load: Option<PathBuf>,
+
+    /// Don't read or write the scan cache in $XDG_CACHE_HOME/df2
+    #[arg(long, default_value_t = false)]
+    pub no_cache: bool,
+
+    /// Group sizes by file extension instead of by directory entry
+    #[arg(long, default_value_t = false, conflicts_with_all = ["by_age", "by_owner"])]
+    pub by_type: bool,
+
+    /// Group sizes by how long ago files were modified or accessed
+    #[arg(
+        long,
+        value_enum,
+        value_name = "TIME",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "mtime",
+        conflicts_with_all = ["by_type", "by_owner"]
+    )]
+    pub by_age: Option<TimeField>,
+
+    /// Group sizes by the user or group owning the files
+    #[arg(
+        long,
+        value_enum,
+        value_name = "OWNER",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "user",
+        conflicts_with_all = ["by_type", "by_age"]
+    )]
+    pub by_owner: Option<OwnerField>,
+
+    /// Fold entries smaller than SIZE (e.g. 10MB) into "Other"
+    #[arg(long, value_name = "SIZE")]
+    pub min_size: Option<ByteSize>,
+
+    /// Fold entries smaller than PERCENT of the total into "Other"
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pub min_percent: Option<f64>,
+
+    /// What to order entries by in the chart, the item list and the navigator
+    #[arg(long, value_enum, default_value_t = SortKey::Size)]
+    pub sort: SortKey,
+
+    /// Sort order [default: asc for name, desc otherwise]
+    #[arg(long, value_enum)]
+    pub order: Option<SortOrder>,
+
+    /// List the N largest files found anywhere in the tree
+    #[arg(long, value_name = "N")]
+    pub top_files: Option<usize>,
+
+    /// Print a plain table once the scan finishes, without live progress.
+    /// This is the default when stdout isn't a terminal
+    #[arg(long, default_value_t = false, conflicts_with = "interactive_mode")]
+    pub plain: bool,
+
+    /// When to use colors
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, global = true)]
+    pub color: ColorChoice,
+
+    /// Keep the view open and rescan every INTERVAL (e.g. 30s, 5m), showing how
+    /// much each entry grew since the previous pass
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        value_parser = parse_interval,
+        conflicts_with_all = ["interactive_mode", "load", "plain"]
+    )]
+    pub watch: Option<Duration>,
+
+    /// Keep the view open and update it as files change, falling back to
+    /// rescanning every --watch interval (30s by default) if the tree can't be watched
+    #[arg(long, default_value_t = false, conflicts_with_all = ["interactive_mode", "load", "plain"])]
+    pub live: bool,
+}
+
+impl ScanJobArgs {
+    // Scans from the command line go through the same options as the library
+    pub fn scan_options(&self) -> anyhow::Result<ScanOptions> {
+        Ok(ScanOptions {
+            record_tree: self.interactive_mode || self.breakdown().is_some(),
+            use_cache: !self.no_cache,
+            ..self.scanner().options()?
+        })
+    }
+
+    pub fn is_plain(&self) -> bool {
+        self.plain || !io::stdout().is_terminal()
+    }
+
+    pub fn scanner(&self) -> Scanner {
+        self.scan
+            .scanner(&self.directory)
+            .hard_links(self.hard_links)
+            .size_mode(self.size_mode())
+            .top_files(self.top_files.unwrap_or(0))
+    }
+
+    pub fn breakdown(&self) -> Option<Breakdown> {
+        match (self.by_type, self.by_age, self.by_owner) {
+            (true, _, _) => Some(Breakdown::Type),
+            (_, Some(field), _) => Some(Breakdown::Age(field)),
+            (_, _, Some(field)) => Some(Breakdown::Owner(field)),
+            _ => None,
+        }
+    }
+
+    pub fn size_mode(&self) -> SizeMode {
+        match self.disk_usage {
+            true => SizeMode::Disk,
+            false => SizeMode::Apparent,
+        }
+    }
+
+    pub fn sort_order(&self) -> SortOrder {
+        self.order.unwrap_or_else(|| self.sort.default_order())
+    }
+
+    pub fn secondary_size_mode(&self) -> Option<SizeMode> {
+        match (self.both_sizes, self.size_mode()) {
+            (false, _) => None,
+            (true, SizeMode::Apparent) => Some(SizeMode::Disk),
+            (true, SizeMode::Disk) => Some(SizeMode::Apparent),
+        }
+    }
+}
+
+impl ScanArgs {
+    pub fn scanner(&self, directory: &str) -> Scanner {
+        let scanner = Scanner::new(directory)
+            .one_file_system(self.one_file_system)
+            .ignore_mode(self.ignore_mode())
+            .threads(self.threads.unwrap_or_default());
+        let scanner = self
+            .exclude
+            .iter()
+            .fold(scanner, |scanner, glob| scanner.exclude(glob));
+        let scanner = self
+            .exclude_from
+            .iter()
+            .fold(scanner, |scanner, file| scanner.exclude_from(file));
+        self.include
+            .iter()
+            .fold(scanner, |scanner, glob| scanner.include(glob))
+    }
+
+    pub fn ignore_mode(&self) -> IgnoreMode {
+        match (self.respect_ignore, self.only_ignored) {
+            (true, _) => IgnoreMode::Respect,
+            (_, true) => IgnoreMode::OnlyIgnored,
+            _ => IgnoreMode::Off,
+        }
+    }
+}
+
+impl DiffArgs {
+    pub fn size_mode(&self) -> SizeMode {
+        match self.disk_usage {
+            true => SizeMode::Disk,
+            false => SizeMode::Apparent,
+        }
+    }
+}
+
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let interval = humantime::parse_duration(value).map_err(|e| e.to_string())?;
+    match interval < MIN_WATCH_INTERVAL {
+        true => Err(format!(
+            "must be at least {}",
+            humantime::format_duration(MIN_WATCH_INTERVAL)
+        )),
+        false => Ok(interval),
+    }
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent: f64 = value
+        .parse()
+        .map_err(|_| format!("'{value}' isn't a number"))?;
+    match (0.0..=100.0).contains(&percent) {
+        true => Ok(percent),
+        false => Err(String::from("must be between 0 and 100")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_short_watch_intervals() {
+        assert_eq!(parse_interval("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_interval("1s"), Ok(MIN_WATCH_INTERVAL));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("500ms").is_err());
+        assert!(parse_interval("soon").is_err());
+    }
+
+    #[test]
+    fn rejects_percentages_out_of_range() {
+        assert_eq!(parse_percent("0"), Ok(0.0));
+        assert_eq!(parse_percent("2.5"), Ok(2.5));
+        assert_eq!(parse_percent("100"), Ok(100.0));
+        assert!(parse_percent("-1").is_err());
+        assert!(parse_percent("150").is_err());
+        assert!(parse_percent("NaN").is_err());
+        assert!(parse_percent("lots").is_err());
+    }
+
+    #[test]
+    fn dupes_take_the_scan_options() {
+        let args =
+            ScanJobArgs::parse_from(["df2", "dupes", "-x", "-e", "*.tmp", "--limit", "3", "src"]);
+        let Some(Command::Dupes(dupes)) = args.command else {
+            panic!("expected the dupes subcommand");
+        };
+        assert_eq!(dupes.directory, "src");
+        assert_eq!(dupes.limit, Some(3));
+        assert!(dupes.scan.one_file_system);
+        assert_eq!(dupes.scan.exclude, ["*.tmp"]);
+    }
+
+    #[test]
+    fn diff_takes_two_scans() {
+        let args = ScanJobArgs::parse_from(["df2", "diff", "-u", "old.json", "."]);
+        let Some(Command::Diff(diff)) = args.command else {
+            panic!("expected the diff subcommand");
+        };
+        assert_eq!(diff.old, PathBuf::from("old.json"));
+        assert_eq!(diff.new, PathBuf::from("."));
+        assert_eq!(diff.size_mode(), SizeMode::Disk);
+    }
 }
@@ -0,0 +1,352 @@
+use super::file_util::{
+    AtomicTimes, AtomicUsage, EntryTimes, SizeMode, SortFields, SortKey, SortOrder, Usage,
+};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Directory,
+    File,
+    Symlink,
+}
+
+/// A file's own size and identity, whichever share of it was counted.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct FileStat {
+    pub size: Usage,
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
+}
+
+impl FileStat {
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        Self {
+            size: Usage::from_metadata(metadata),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            nlink: metadata.nlink(),
+        }
+    }
+
+    pub fn is_hard_link(&self) -> bool {
+        self.nlink > 1
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanNode {
+    pub name: String,
+    pub kind: NodeKind,
+    pub usage: Usage,
+    pub mtime: Option<i64>,
+    pub atime: Option<i64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Newest times of the entry and of everything in it.
+    pub newest: EntryTimes,
+    /// What the file itself takes up, for files.
+    pub stat: Option<FileStat>,
+    pub children: Vec<ScanNode>,
+}
+
+impl ScanNode {
+    pub fn file(
+        name: String,
+        kind: NodeKind,
+        usage: Usage,
+        metadata: Option<&fs::Metadata>,
+    ) -> Self {
+        Self {
+            name,
+            kind,
+            usage,
+            mtime: metadata.map(|m| m.mtime()),
+            atime: metadata.map(|m| m.atime()),
+            uid: metadata.map(|m| m.uid()),
+            gid: metadata.map(|m| m.gid()),
+            newest: EntryTimes::from_metadata(metadata),
+            stat: metadata.map(FileStat::from_metadata),
+            children: Vec::new(),
+        }
+    }
+
+    /// Orders children largest first, recursively.
+    pub fn sort_by_size(&mut self, size_mode: SizeMode) {
+        self.sort_by(SortKey::Size, SortOrder::Desc, size_mode);
+    }
+
+    /// Orders children by `key`, recursively.
+    pub fn sort_by(&mut self, key: SortKey, order: SortOrder, size_mode: SizeMode) {
+        self.children.sort_by(|a, b| {
+            let (a, b) = (a.sort_fields(size_mode), b.sort_fields(size_mode));
+            match order {
+                SortOrder::Asc => key.compare(a, b),
+                SortOrder::Desc => key.compare(b, a),
+            }
+        });
+        for child in &mut self.children {
+            child.sort_by(key, order, size_mode);
+        }
+    }
+
+    pub fn times(&self) -> EntryTimes {
+        EntryTimes {
+            mtime: self.mtime,
+            atime: self.atime,
+        }
+    }
+
+    fn sort_fields(&self, size_mode: SizeMode) -> SortFields<'_> {
+        SortFields {
+            name: &self.name,
+            size: self.usage.get(size_mode),
+            files: self.usage.files,
+            times: self.newest,
+        }
+    }
+
+    pub fn find(&self, path: &Path) -> Option<&ScanNode> {
+        let relative = path.strip_prefix(&self.name).ok()?;
+        relative
+            .components()
+            .try_fold(self, |node, component| match component {
+                Component::Normal(name) => node.children.iter().find(|child| {
+                    child.kind == NodeKind::Directory && name.to_str() == Some(&child.name)
+                }),
+                Component::CurDir => Some(node),
+                _ => None,
+            })
+    }
+
+    /// Takes the entry at `relative` out of the tree, along with its usage
+    /// from every directory above it.
+    pub fn remove(&mut self, relative: &Path) -> Option<ScanNode> {
+        self.remove_at(&normal_components(relative))
+    }
+
+    /// Adds `node` to the directory at `parent`, and its usage to every
+    /// directory above it. Returns false if there's no such directory.
+    pub fn insert(&mut self, parent: &Path, node: ScanNode) -> bool {
+        self.insert_at(&normal_components(parent), node)
+    }
+
+    fn remove_at(&mut self, names: &[&OsStr]) -> Option<ScanNode> {
+        let (name, rest) = names.split_first()?;
+        let index = self
+            .children
+            .iter()
+            .position(|child| OsStr::new(&child.name) == *name)?;
+        let removed = match rest.is_empty() {
+            true => self.children.remove(index),
+            false => self.children[index].remove_at(rest)?,
+        };
+        self.usage = self.usage.saturating_sub(removed.usage);
+        self.refresh_newest();
+        Some(removed)
+    }
+
+    fn insert_at(&mut self, names: &[&OsStr], node: ScanNode) -> bool {
+        let (usage, newest) = (node.usage, node.newest);
+        let inserted = match names.split_first() {
+            None => {
+                self.children.push(node);
+                true
+            }
+            Some((name, rest)) => self
+                .children
+                .iter_mut()
+                .find(|child| child.kind == NodeKind::Directory && OsStr::new(&child.name) == *name)
+                .is_some_and(|child| child.insert_at(rest, node)),
+        };
+        if inserted {
+            self.usage += usage;
+            self.newest = self.newest.newest(newest);
+        }
+        inserted
+    }
+
+    /// Works out `newest` again from the entry's own times and its children's.
+    pub fn refresh_newest(&mut self) {
+        self.newest = self
+            .children
+            .iter()
+            .fold(self.times(), |newest, child| newest.newest(child.newest));
+    }
+}
+
+/// A file of `size` bytes, for tests.
+#[cfg(test)]
+pub(crate) fn file(name: &str, size: u64) -> ScanNode {
+    let usage = Usage {
+        apparent: size,
+        disk: size,
+        files: 1,
+    };
+    ScanNode::file(name.to_string(), NodeKind::File, usage, None)
+}
+
+/// A directory with the usage and times of `children`, for tests.
+#[cfg(test)]
+pub(crate) fn dir(name: &str, children: Vec<ScanNode>) -> ScanNode {
+    let mut node = ScanNode::file(
+        name.to_string(),
+        NodeKind::Directory,
+        Usage::default(),
+        None,
+    );
+    for child in &children {
+        node.usage += child.usage;
+    }
+    node.children = children;
+    node.refresh_newest();
+    node
+}
+
+fn normal_components(path: &Path) -> Vec<&OsStr> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub(super) enum PendingEntry {
+    Directory(Arc<PendingDir>),
+    File(Box<ScanNode>),
+}
+
+// A directory whose contents are still being scanned
+#[derive(Debug)]
+pub(super) struct PendingDir {
+    name: String,
+    mtime: Option<i64>,
+    atime: Option<i64>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    size: Arc<AtomicUsage>,
+    times: Arc<AtomicTimes>,
+    entries: Mutex<Vec<PendingEntry>>,
+}
+
+impl PendingDir {
+    pub fn new(
+        name: String,
+        metadata: Option<&fs::Metadata>,
+        size: Arc<AtomicUsage>,
+        times: Arc<AtomicTimes>,
+    ) -> Self {
+        Self {
+            name,
+            mtime: metadata.map(|m| m.mtime()),
+            atime: metadata.map(|m| m.atime()),
+            uid: metadata.map(|m| m.uid()),
+            gid: metadata.map(|m| m.gid()),
+            size,
+            times,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn push(&self, entry: PendingEntry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    pub fn finish(&self) -> ScanNode {
+        let entries = std::mem::take(&mut *self.entries.lock().unwrap());
+        ScanNode {
+            name: self.name.clone(),
+            kind: NodeKind::Directory,
+            usage: self.size.load(),
+            mtime: self.mtime,
+            atime: self.atime,
+            uid: self.uid,
+            gid: self.gid,
+            newest: self.times.load(),
+            stat: None,
+            children: entries
+                .into_iter()
+                .map(|entry| match entry {
+                    PendingEntry::Directory(dir) => dir.finish(),
+                    PendingEntry::File(node) => *node,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> ScanNode {
+        dir(
+            "/root",
+            vec![dir("a", vec![dir("b", vec![file("x", 10)])]), file("y", 5)],
+        )
+    }
+
+    #[test]
+    fn removes_entries_and_their_usage() {
+        let mut tree = tree();
+        let removed = tree.remove(Path::new("a/b/x")).unwrap();
+        assert_eq!(removed.usage.apparent, 10);
+        assert_eq!(tree.usage.apparent, 5);
+        assert_eq!(tree.usage.files, 1);
+        let b = tree.find(Path::new("/root/a/b")).unwrap();
+        assert_eq!((b.usage.apparent, b.children.len()), (0, 0));
+
+        assert!(tree.remove(Path::new("a/missing")).is_none());
+        assert!(tree.remove(Path::new("y/z")).is_none());
+        assert_eq!(tree.usage.apparent, 5);
+    }
+
+    #[test]
+    fn inserts_entries_and_their_usage() {
+        let mut tree = tree();
+        assert!(tree.insert(Path::new("a/b"), file("z", 7)));
+        assert_eq!(tree.usage.apparent, 22);
+        let a = tree.find(Path::new("/root/a")).unwrap();
+        assert_eq!((a.usage.apparent, a.usage.files), (17, 2));
+
+        assert!(tree.insert(Path::new(""), file("w", 1)));
+        assert_eq!(tree.usage.apparent, 23);
+
+        // Files can't hold entries, and missing directories aren't created
+        assert!(!tree.insert(Path::new("y"), file("v", 1)));
+        assert!(!tree.insert(Path::new("a/c"), file("v", 1)));
+        assert_eq!(tree.usage.apparent, 23);
+    }
+
+    #[test]
+    fn keeps_the_newest_times_up_to_date() {
+        let mut tree = tree();
+        let times = EntryTimes {
+            mtime: Some(50),
+            atime: None,
+        };
+        let z = ScanNode {
+            mtime: times.mtime,
+            newest: times,
+            ..file("z", 7)
+        };
+        assert!(tree.insert(Path::new("a/b"), z));
+        assert_eq!(tree.newest, times);
+        assert_eq!(tree.find(Path::new("/root/a")).unwrap().newest, times);
+
+        tree.remove(Path::new("a/b/z")).unwrap();
+        assert_eq!(tree.newest, EntryTimes::default());
+        assert_eq!(
+            tree.find(Path::new("/root/a")).unwrap().newest,
+            EntryTimes::default()
+        );
+    }
+}
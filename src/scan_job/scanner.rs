@@ -0,0 +1,200 @@
+use super::dupes::{DupeCandidates, DuplicateSet};
+use super::export::ScanMetadata;
+use super::file_util::{
+    get_dir_size, ErrorHandler, HardLinkPolicy, ScanOptions, ScanStats, SizeMode, ThreadCount,
+    Usage,
+};
+use super::ignore_rules::IgnoreMode;
+use super::path_filter::PathFilter;
+use super::scan_cache::ScanCache;
+use super::scan_tree::ScanNode;
+use super::top_files::{TopFile, TopFiles};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Builder for a single scan of a directory tree.
+///
+/// ```no_run
+/// let result = df2::Scanner::new("/var/log")
+///     .exclude("*.gz")
+///     .threads(df2::ThreadCount::Fixed(4))
+///     .scan()
+///     .unwrap();
+/// println!("{} bytes", result.size());
+/// ```
+pub struct Scanner {
+    root: String,
+    exclude: Vec<String>,
+    exclude_from: Vec<PathBuf>,
+    include: Vec<String>,
+    options: ScanOptions,
+    size_mode: SizeMode,
+    top_files: usize,
+    on_error: Arc<ErrorHandler>,
+}
+
+/// Outcome of a scan, with the tree's children sorted largest first.
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    pub metadata: ScanMetadata,
+    pub tree: ScanNode,
+    pub size_mode: SizeMode,
+    pub hard_link_savings: Usage,
+    pub excluded: Usage,
+    pub skipped_mounts: Vec<String>,
+    /// Largest files anywhere in the tree, largest first.
+    pub top_files: Vec<TopFile>,
+}
+
+impl ScanResult {
+    /// Total size of the scanned root in the result's size mode.
+    pub fn size(&self) -> u64 {
+        self.tree.usage.get(self.size_mode)
+    }
+}
+
+impl Scanner {
+    pub fn new(root: impl Into<String>) -> Self {
+        Self {
+            root: root.into(),
+            exclude: Vec::new(),
+            exclude_from: Vec::new(),
+            include: Vec::new(),
+            options: ScanOptions::default(),
+            size_mode: SizeMode::Apparent,
+            top_files: 0,
+            on_error: Arc::new(|_| {}),
+        }
+    }
+
+    /// Leave out files and directories matching `glob`.
+    pub fn exclude(mut self, glob: impl Into<String>) -> Self {
+        self.exclude.push(glob.into());
+        self
+    }
+
+    /// Read exclude globs from `file`, one per line.
+    pub fn exclude_from(mut self, file: impl Into<PathBuf>) -> Self {
+        self.exclude_from.push(file.into());
+        self
+    }
+
+    /// Only count files matching `glob`, or inside a directory matching it.
+    pub fn include(mut self, glob: impl Into<String>) -> Self {
+        self.include.push(glob.into());
+        self
+    }
+
+    pub fn ignore_mode(mut self, ignore_mode: IgnoreMode) -> Self {
+        self.options.ignore_mode = ignore_mode;
+        self
+    }
+
+    pub fn hard_links(mut self, hard_links: HardLinkPolicy) -> Self {
+        self.options.hard_links = hard_links;
+        self
+    }
+
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+        self.options.one_file_system = one_file_system;
+        self
+    }
+
+    pub fn threads(mut self, threads: ThreadCount) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Size used to order the result tree.
+    pub fn size_mode(mut self, size_mode: SizeMode) -> Self {
+        self.size_mode = size_mode;
+        self
+    }
+
+    /// Keep track of the `count` largest files in the result.
+    pub fn top_files(mut self, count: usize) -> Self {
+        self.top_files = count;
+        self
+    }
+
+    /// Called with a message for every entry that couldn't be read.
+    pub fn on_error<F>(mut self, on_error: F) -> Self
+    where
+        F: Fn(String) + Send + Sync + 'static,
+    {
+        self.on_error = Arc::new(on_error);
+        self
+    }
+
+    pub(crate) fn options(&self) -> anyhow::Result<ScanOptions> {
+        Ok(ScanOptions {
+            filter: PathFilter::new(&self.exclude, &self.exclude_from, &self.include)?,
+            record_tree: false,
+            use_cache: false,
+            ..self.options.clone()
+        })
+    }
+
+    pub fn scan(self) -> anyhow::Result<ScanResult> {
+        let options = ScanOptions {
+            record_tree: true,
+            ..self.options()?
+        };
+        let stats = Arc::new(ScanStats {
+            top_files: TopFiles::new(self.top_files, self.size_mode),
+            ..Default::default()
+        });
+        let started_at = SystemTime::now();
+        let start_time = Instant::now();
+
+        let mut tree = get_dir_size(
+            &self.root,
+            options,
+            stats.clone(),
+            Arc::new(ScanCache::in_memory()),
+            None,
+            self.on_error,
+        )
+        .ok_or_else(|| anyhow::anyhow!("Scan did not produce a tree"))?;
+        tree.sort_by_size(self.size_mode);
+        let skipped_mounts = stats.skipped_mounts.lock().unwrap().clone();
+
+        Ok(ScanResult {
+            metadata: ScanMetadata {
+                root: self.root,
+                started_at: started_at.duration_since(UNIX_EPOCH)?.as_secs(),
+                duration_ms: start_time.elapsed().as_millis() as u64,
+                error_count: stats.errors.load(Ordering::Relaxed),
+            },
+            tree,
+            size_mode: self.size_mode,
+            hard_link_savings: stats.hard_link_savings.load(),
+            excluded: stats.excluded.load(),
+            skipped_mounts,
+            top_files: stats.top_files.sorted(),
+        })
+    }
+
+    /// Find files with identical contents, most wasted space first. Hard links
+    /// to the same file aren't duplicates of each other.
+    pub fn find_duplicates(self) -> anyhow::Result<Vec<DuplicateSet>> {
+        let options = self.options()?;
+        let threads = options.threads.resolve();
+        let stats = Arc::new(ScanStats {
+            duplicates: DupeCandidates::new(true),
+            ..Default::default()
+        });
+
+        get_dir_size(
+            &self.root,
+            options,
+            stats.clone(),
+            Arc::new(ScanCache::in_memory()),
+            None,
+            self.on_error.clone(),
+        );
+        Ok(stats.duplicates.confirm(threads, self.on_error.as_ref()))
+    }
+}
@@ -0,0 +1,162 @@
+use super::file_util::{SizeMode, Usage};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct TopFile {
+    pub path: String,
+    pub usage: Usage,
+    pub mtime: Option<i64>,
+}
+
+impl TopFile {
+    pub fn size(&self, size_mode: SizeMode) -> u64 {
+        self.usage.get(size_mode)
+    }
+}
+
+// A kept file, ordered by its size in the size mode of the scan
+#[derive(Debug, Clone)]
+struct Ranked {
+    size: u64,
+    file: TopFile,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.size
+            .cmp(&other.size)
+            .then_with(|| self.file.path.cmp(&other.file.path))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+// The largest files seen so far, kept in a min-heap of at most `limit` entries
+#[derive(Debug, Default)]
+pub struct TopFiles {
+    limit: usize,
+    size_mode: SizeMode,
+    // Size of the smallest kept file once the heap is full, so most files
+    // can be turned away without taking the lock
+    threshold: AtomicU64,
+    heap: Mutex<BinaryHeap<Reverse<Ranked>>>,
+}
+
+impl TopFiles {
+    pub fn new(limit: usize, size_mode: SizeMode) -> Self {
+        Self {
+            limit,
+            size_mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    pub fn offer<F>(&self, path: F, usage: Usage, mtime: Option<i64>)
+    where
+        F: FnOnce() -> String,
+    {
+        let size = usage.get(self.size_mode);
+        if !self.is_enabled() || size <= self.threshold.load(atomic::Ordering::Relaxed) {
+            return;
+        }
+
+        let mut heap = self.heap.lock().unwrap();
+        heap.push(Reverse(Ranked {
+            size,
+            file: TopFile {
+                path: path(),
+                usage,
+                mtime,
+            },
+        }));
+        if heap.len() > self.limit {
+            heap.pop();
+        }
+        if heap.len() == self.limit {
+            let Reverse(smallest) = heap.peek().unwrap();
+            self.threshold
+                .store(smallest.size, atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// Largest first.
+    pub fn sorted(&self) -> Vec<TopFile> {
+        self.heap
+            .lock()
+            .unwrap()
+            .clone()
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(ranked)| ranked.file)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(apparent: u64, disk: u64) -> Usage {
+        Usage {
+            apparent,
+            disk,
+            files: 1,
+        }
+    }
+
+    #[test]
+    fn keeps_the_largest_files() {
+        let top_files = TopFiles::new(3, SizeMode::Apparent);
+        for (i, size) in [5, 1, 9, 3, 7, 9].into_iter().enumerate() {
+            top_files.offer(|| format!("f{}", i), usage(size, 0), None);
+        }
+        let sizes = top_files
+            .sorted()
+            .iter()
+            .map(|file| (file.path.clone(), file.size(SizeMode::Apparent)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            [
+                ("f5".to_string(), 9),
+                ("f2".to_string(), 9),
+                ("f4".to_string(), 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn ranks_by_the_size_mode() {
+        let top_files = TopFiles::new(1, SizeMode::Disk);
+        top_files.offer(|| "sparse".to_string(), usage(1000, 0), None);
+        top_files.offer(|| "dense".to_string(), usage(10, 4096), None);
+        assert_eq!(top_files.sorted()[0].path, "dense");
+    }
+
+    #[test]
+    fn is_disabled_without_a_limit() {
+        let top_files = TopFiles::new(0, SizeMode::Apparent);
+        assert!(!top_files.is_enabled());
+        top_files.offer(|| panic!("path of a disabled offer"), usage(1, 1), None);
+        assert!(top_files.sorted().is_empty());
+    }
+}